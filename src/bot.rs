@@ -1,19 +1,34 @@
 use crate::{
-    command::Command,
+    command::{parse_callback, CallbackCmd, Command},
     commands::{
         activity::get_activity_inputfile,
-        queue::{add_remove, list, remove_all},
+        admin::{clear, clear_all, kick},
+        attendance::reliability,
+        auto_add::auto,
+        avail::avail,
+        history::queuestats,
+        queue::{
+            add_remove, extend, join_leave, list, move_queue, queue_size, ready, ready_button,
+            remove_all,
+        },
+        recurring::recurring,
         sahko::get_sahko_inputfile,
         stats::{hall_of_fame, hall_of_shame, last_played, stat_leaderboard, stats},
+        subscription::{subscribe, unsubscribe},
+        teams::{draft, pick, teams},
+        veto::{veto, veto_button},
         weather::{temperature, weather as weather_report},
+        Context,
     },
     settings::Settings,
+    state::JoinMode,
     state_container::StateContainer,
-    util::{mk_username, send_msg, send_photo},
+    types::Username,
+    util::{mk_ambiguous_name_msg, mk_player, send_msg, send_photo},
 };
 
 use chrono_tz::Tz;
-use teloxide::{prelude::*, Bot};
+use teloxide::{prelude::*, types::CallbackQuery, Bot};
 
 /// Handler for parsed incoming Telegram commands.
 pub async fn handle_cmd(
@@ -24,31 +39,138 @@ pub async fn handle_cmd(
     msg: Message,
     cmd: Command,
 ) -> Option<()> {
-    let state = sc.read().await;
+    let mut state = sc.read().await;
     let chat_id = msg.chat.id;
     let user = msg.from?;
+    let player = mk_player(&user);
+
+    // Keep track of the user's current name, and attach their user id to any
+    // queue entries that were added by name.
+    let identified = sc
+        .update(|state| Some((state.identify_player(&player)?, ())))
+        .await;
+    if identified.is_some() {
+        state = sc.read().await;
+    }
+
+    // Resolves `@username` arguments, or returns an error message to reply
+    // with if the name is ambiguous.
+    let by_name = |name: Username| {
+        state
+            .player_by_name(name.clone())
+            .ok_or_else(|| mk_ambiguous_name_msg(&name))
+    };
+
+    // Resolves optional `@username` arguments, defaulting to the user.
+    let for_player = |for_user: Option<Username>| match for_user {
+        Some(name) => by_name(name),
+        None => Ok(player.clone()),
+    };
+
+    let ctx = Context {
+        bot: &bot,
+        settings: &settings,
+        sc: &sc,
+        tz: &tz,
+        chat_id,
+    };
 
     let markdown = matches!(cmd, Command::Help);
 
     let text = match cmd {
         Command::Help => Command::help(),
-        Command::AddRemove { time, for_user } => {
-            let username = for_user.unwrap_or_else(|| mk_username(&user));
-            add_remove(username, state, chat_id, &tz, time, &sc).await
-        }
+        Command::AddRemove {
+            time,
+            for_user,
+            size,
+            mode,
+        } => match for_player(for_user) {
+            Ok(for_player) => {
+                add_remove(ctx, for_player, &player, state, time, size, mode).await;
+                return Some(());
+            }
+            Err(text) => text,
+        },
+        Command::QueueSize { size } => queue_size(ctx, size).await,
         Command::RemoveAll => {
-            let username = mk_username(&user);
-            remove_all(username, state, chat_id, &sc).await
+            remove_all(ctx, player).await;
+            return Some(());
         }
-        Command::List => list(state, chat_id, &tz),
-        Command::Stats { for_user } => {
-            let username = for_user.unwrap_or_else(|| mk_username(&user));
-            stats(&settings, &username).await
+        Command::Move { from, to, merge } => {
+            move_queue(ctx, from, to, merge).await;
+            return Some(());
         }
-        Command::LastPlayed { for_user } => {
-            let username = for_user.unwrap_or_else(|| mk_username(&user));
-            last_played(&settings, &tz, username).await
+        Command::Extend { minutes } => extend(ctx, minutes).await,
+        Command::Kick { user, time } => match by_name(user) {
+            Ok(for_player) => {
+                kick(ctx, &player, for_player, time).await;
+                return Some(());
+            }
+            Err(text) => text,
+        },
+        Command::Clear { time } => {
+            clear(ctx, &player, time).await;
+            return Some(());
         }
+        Command::ClearAll => {
+            clear_all(ctx, &player).await;
+            return Some(());
+        }
+        Command::Subscribe {
+            min_players,
+            window,
+            dm,
+        } => subscribe(ctx, player, min_players, window, dm).await,
+        Command::Unsubscribe => unsubscribe(ctx, player).await,
+        Command::Auto(cmd) => auto(ctx, player, cmd).await,
+        Command::Avail(cmd) => {
+            avail(ctx, player, cmd).await;
+            return Some(());
+        }
+        Command::Draft { time, captains } => match captains.into_iter().map(by_name).collect() {
+            Ok(captains) => {
+                draft(ctx, state, time, captains).await;
+                return Some(());
+            }
+            Err(text) => text,
+        },
+        Command::Veto { best_of, sides } => match sides.into_iter().map(by_name).collect() {
+            Ok(sides) => {
+                veto(ctx, best_of, sides).await;
+                return Some(());
+            }
+            Err(text) => text,
+        },
+        Command::QueueStats { csv } => {
+            queuestats(ctx, state, csv).await;
+            return Some(());
+        }
+        Command::Reliability => reliability(&state, chat_id),
+        Command::Teams {
+            time,
+            rating,
+            reroll,
+        } => teams(ctx, state, time, rating, reroll).await,
+        Command::Ready => {
+            ready(ctx, player, state).await;
+            return Some(());
+        }
+        Command::List => list(state, chat_id),
+        Command::Recurring(cmd) => recurring(ctx, cmd).await,
+        Command::Stats { for_user } => match for_player(for_user) {
+            Ok(for_player) => {
+                let username = settings.players.mapping_username(&for_player);
+                stats(&settings, &username).await
+            }
+            Err(text) => text,
+        },
+        Command::LastPlayed { for_user } => match for_player(for_user) {
+            Ok(for_player) => {
+                let username = settings.players.mapping_username(&for_player);
+                last_played(&settings, &tz, username).await
+            }
+            Err(text) => text,
+        },
         Command::HallOfShame => hall_of_shame(&settings, &tz).await,
         Command::HallOfFame { rank_type } => hall_of_fame(&settings, rank_type).await,
         Command::Temperature => temperature().await,
@@ -66,6 +188,14 @@ pub async fn handle_cmd(
             return Some(());
         }
         Command::Activity { for_user } => {
+            let for_user = match for_user.map(by_name).transpose() {
+                Ok(for_user) => for_user.map(|player| settings.players.mapping_username(&player)),
+                Err(text) => {
+                    send_msg(&bot, &chat_id, &text, false).await;
+                    return Some(());
+                }
+            };
+
             let photo = match get_activity_inputfile(&settings, for_user.as_ref()).await {
                 Ok(photo) => photo,
                 Err(e) => {
@@ -84,3 +214,55 @@ pub async fn handle_cmd(
 
     Some(())
 }
+
+/// Handler for incoming Telegram inline keyboard button presses.
+pub async fn handle_callback_query(
+    settings: Settings,
+    sc: StateContainer,
+    tz: Tz,
+    bot: Bot,
+    query: CallbackQuery,
+) -> Option<()> {
+    let mut state = sc.read().await;
+    let chat_id = query.message.as_ref()?.chat().id;
+    let player = mk_player(&query.from);
+
+    let identified = sc
+        .update(|state| Some((state.identify_player(&player)?, ())))
+        .await;
+    if identified.is_some() {
+        state = sc.read().await;
+    }
+
+    let cmd = parse_callback(query.data.as_deref()?)?;
+    let ctx = Context {
+        bot: &bot,
+        settings: &settings,
+        sc: &sc,
+        tz: &tz,
+        chat_id,
+    };
+
+    let text = match cmd {
+        CallbackCmd::Join { queue_id } => {
+            let mode = Some(JoinMode::Yes);
+            join_leave(ctx, player, state, queue_id, mode).await
+        }
+        CallbackCmd::Maybe { queue_id } => {
+            let mode = Some(JoinMode::Maybe);
+            join_leave(ctx, player, state, queue_id, mode).await
+        }
+        CallbackCmd::Leave { queue_id } => join_leave(ctx, player, state, queue_id, None).await,
+        CallbackCmd::Ready { queue_id } => ready_button(ctx, player, queue_id).await,
+        CallbackCmd::Pick { queue_id, index } => pick(ctx, player, queue_id, index).await,
+        CallbackCmd::Veto { queue_id, map } => veto_button(ctx, player, queue_id, map).await,
+    };
+
+    let res = bot.answer_callback_query(query.id).text(text).send().await;
+
+    if let Err(error) = res {
+        eprintln!("Error while answering Telegram callback query: {}", error);
+    }
+
+    Some(())
+}
@@ -1,10 +1,34 @@
-use chrono::NaiveTime;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::types::Username;
+use crate::{
+    services::leetify::TeamRating,
+    state::{JoinMode, MAX_QUEUE_SIZE},
+    types::{QueueId, Username},
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Maps known by name, e.g. `/mirage` for the `de_mirage` hall of fame.
+pub const MAP_NAMES: [&str; 12] = [
+    "cs_office",
+    "cs_italy",
+    "de_mirage",
+    "de_overpass",
+    "de_inferno",
+    "de_nuke",
+    "de_train",
+    "de_vertigo",
+    "de_dust2",
+    "de_cache",
+    "de_ancient",
+    "de_anubis",
+];
+
+/// Longest accepted extension of the instant queue, in minutes.
+const MAX_EXTEND_MINUTES: i64 = 24 * 60;
 lazy_static! {
     pub static ref HELP_TEXT: String = format!(
         "add-bot v{VERSION}
@@ -12,9 +36,37 @@ lazy_static! {
 The following commands are supported:
 ```
 - /1930         Add/remove player from timed queue at 19:30.
+- /fri1930      Add/remove player from timed queue on Friday at 19:30.
 - /add          Add/remove player from the instant queue.
 - /ls           List existing queues.
 - /rm           Remove yourself from all queues.
+- /ready        Confirm you're ready when a queue is full.
+- /queuesize    Show or set default queue size for this chat.
+- /move         Move a queue, e.g. /move 1930 2000 [merge].
+- /extend       Extend the instant queue by 30 (or given) minutes.
+- /kick         Remove a player you added, e.g. /kick @user [1930].
+- /clear        Clear the instant queue or e.g. /clear 1930 (admins).
+- /clearall     Clear all queues (admins).
+- /subscribe    Get pinged when a queue reaches N players, e.g.
+                /subscribe 3 [1800-2200] [dm]
+- /unsubscribe  Stop queue alerts.
+- /auto         List your availability rules. Subcommands:
+                add weekdays 1900-2100
+                rm <id>
+- /avail        Post when you can play, e.g. /avail 1900-2230 [fri].
+                A queue opens once enough players overlap.
+                /avail rm removes your range.
+- /teams        Balance teams of a 10 player queue by premier rating.
+                /teams [1930] [leetify] [reroll]
+- /draft        Captain draft for a 10 player queue, e.g.
+                /draft [1930] [@captain1 @captain2]
+- /veto         Ban maps for the latest match, e.g.
+                /veto [bo1|bo3] [@side1 @side2]
+- /queuestats   Queue history stats, /queuestats csv exports it.
+- /reliability  How often players showed up to played queues.
+- /recurring    List recurring queues. Subcommands:
+                add tue,thu 1930 [@user...] [size=N]
+                pause|resume|rm <id>
 - /lastplayed   Last played game stats for player.
 - /stats        Leetify stats for player.
 - /halloffame   Top 10 players by skill level.
@@ -28,26 +80,246 @@ The following commands are supported:
 - /activity     Daily games played by all players (last 365 days).
 - /temperature  Current temperature for configured location.
 - /weather      Weather for configured location.
-```Most commands accept an optional `@username` argument, which defaults to yourself."
+```Most commands accept an optional `@username` argument, which defaults to yourself.
+Queue commands also accept `size=N`, e.g. `/1930 size=2` for a wingman queue.
+Add `reserve` to only play if someone in the lineup drops out, e.g. `/1930 reserve`.
+Add `maybe` if you're not sure yet, and `yes` once you are, e.g. `/1930 maybe`.
+Timed queues accept a day: `/1930 tomorrow`, `/1930 fri` or `/1930 2024-12-24`."
     );
 }
 
+/// Day of a timed queue, relative to when the command was sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueDay {
+    /// Next occurrence of the given time, i.e. today or tomorrow.
+    #[default]
+    Next,
+
+    /// Tomorrow, even if the time is still ahead today.
+    Tomorrow,
+
+    /// Next occurrence of the given weekday (today if the time is still ahead).
+    Weekday(Weekday),
+
+    /// An explicit date.
+    Date(NaiveDate),
+}
+
+impl QueueDay {
+    /// Resolves the day and `time` to a point in time in the future, relative
+    /// to `now`. Returns None for times that are in the past or don't exist
+    /// due to DST transitions.
+    pub fn resolve(&self, time: NaiveTime, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let today = now.date_naive();
+
+        let date = match self {
+            QueueDay::Next if today.and_time(time) > now.naive_local() => today,
+            QueueDay::Next | QueueDay::Tomorrow => today + Duration::days(1),
+            QueueDay::Weekday(weekday) => {
+                let days_ahead = (weekday.num_days_from_monday() + 7
+                    - today.weekday().num_days_from_monday())
+                    % 7;
+                let date = today + Duration::days(days_ahead.into());
+
+                if date.and_time(time) > now.naive_local() {
+                    date
+                } else {
+                    date + Duration::weeks(1)
+                }
+            }
+            QueueDay::Date(date) => *date,
+        };
+
+        let t = now.timezone().from_local_datetime(&date.and_time(time));
+        t.earliest().filter(|t| t > now)
+    }
+}
+
+/// Start time of a timed queue, as given in a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueTime {
+    pub time: NaiveTime,
+    pub day: QueueDay,
+}
+
+/// Actions triggered by inline keyboard buttons.
+pub enum CallbackCmd {
+    /// Adds player to queue, if not already in it.
+    Join { queue_id: QueueId },
+
+    /// Adds player to queue as a maybe player.
+    Maybe { queue_id: QueueId },
+
+    /// Removes player from queue, if in it.
+    Leave { queue_id: QueueId },
+
+    /// Confirms player is ready to play in queue.
+    Ready { queue_id: QueueId },
+
+    /// Picks a player in the captain draft of queue.
+    Pick { queue_id: QueueId, index: usize },
+
+    /// Bans or picks a map in the map veto of queue.
+    Veto { queue_id: QueueId, map: usize },
+}
+
+impl CallbackCmd {
+    /// Encodes the action as callback data for an inline keyboard button.
+    pub fn to_data(&self) -> String {
+        match self {
+            CallbackCmd::Join { queue_id } => format!("join:{}", queue_id.as_str()),
+            CallbackCmd::Maybe { queue_id } => format!("maybe:{}", queue_id.as_str()),
+            CallbackCmd::Leave { queue_id } => format!("leave:{}", queue_id.as_str()),
+            CallbackCmd::Ready { queue_id } => format!("ready:{}", queue_id.as_str()),
+            // Queue ids may contain colons, so they go last.
+            CallbackCmd::Pick { queue_id, index } => {
+                format!("pick:{}:{}", index, queue_id.as_str())
+            }
+            CallbackCmd::Veto { queue_id, map } => format!("veto:{}:{}", map, queue_id.as_str()),
+        }
+    }
+}
+
+pub enum AutoCmd {
+    List,
+    Add {
+        weekdays: Vec<Weekday>,
+        from: NaiveTime,
+        to: NaiveTime,
+    },
+    Remove {
+        id: u32,
+    },
+}
+
+pub enum AvailCmd {
+    List,
+    Add {
+        from: NaiveTime,
+        to: NaiveTime,
+        day: QueueDay,
+    },
+    Remove,
+}
+
+pub enum RecurringCmd {
+    List,
+    Add {
+        weekdays: Vec<Weekday>,
+        time: NaiveTime,
+        players: Vec<Username>,
+        size: Option<usize>,
+    },
+    Pause {
+        id: u32,
+    },
+    Resume {
+        id: u32,
+    },
+    Remove {
+        id: u32,
+    },
+}
+
 pub enum Command {
     /// Display help text for supported commands.
     Help,
 
     /// Add/remove player from instant queue or timed queue.
     AddRemove {
-        time: Option<NaiveTime>,
+        time: Option<QueueTime>,
         for_user: Option<Username>,
+        size: Option<usize>,
+        mode: JoinMode,
+    },
+
+    /// Shows or sets default queue size for the chat.
+    QueueSize {
+        size: Option<usize>,
     },
 
     /// Removes player from all queues.
     RemoveAll,
 
+    /// Confirms player is ready in queues with an ongoing ready check.
+    Ready,
+
     /// Lists chat queues.
     List,
 
+    /// Lists or manages recurring queues.
+    Recurring(RecurringCmd),
+
+    /// Moves a timed queue and its players to another time.
+    Move {
+        from: QueueTime,
+        to: QueueTime,
+        merge: bool,
+    },
+
+    /// Extends the instant queue's timeout.
+    Extend {
+        minutes: Option<i64>,
+    },
+
+    /// Removes a player from a timed queue, or from all queues.
+    Kick {
+        user: Username,
+        time: Option<QueueTime>,
+    },
+
+    /// Removes a timed queue, or the instant queue.
+    Clear {
+        time: Option<QueueTime>,
+    },
+
+    /// Removes all queues of the chat.
+    ClearAll,
+
+    /// Shows or sets the player's alert for queues reaching given number of
+    /// players.
+    Subscribe {
+        min_players: Option<usize>,
+        window: Option<(NaiveTime, NaiveTime)>,
+        dm: bool,
+    },
+
+    /// Removes the player's queue alert.
+    Unsubscribe,
+
+    /// Lists or manages the player's availability rules.
+    Auto(AutoCmd),
+
+    /// Lists or posts time ranges when players are available.
+    Avail(AvailCmd),
+
+    /// Starts a captain draft for a queue, optionally with given captains.
+    Draft {
+        time: Option<QueueTime>,
+        captains: Vec<Username>,
+    },
+
+    /// Starts a map veto for the most recently fired queue.
+    Veto {
+        best_of: usize,
+        sides: Vec<Username>,
+    },
+
+    /// Shows stats of the chat's queue history, or exports it as CSV.
+    QueueStats {
+        csv: bool,
+    },
+
+    /// Ranks players by how often they showed up to played queues.
+    Reliability,
+
+    /// Splits players of a queue into two balanced teams.
+    Teams {
+        time: Option<QueueTime>,
+        rating: TeamRating,
+        reroll: bool,
+    },
+
     /// Leetify stats for user
     Stats {
         for_user: Option<Username>,
@@ -128,15 +400,29 @@ fn get_cmd_matches(text: &str) -> Option<CmdMatches> {
     })
 }
 
-/// Checks whether string contains 3-4 digits.
-fn matches_timed_queue(cmd: &str) -> bool {
+/// Checks whether string contains 3-4 digits, optionally prefixed by a
+/// weekday. Returns the weekday prefix and the digits.
+fn matches_timed_queue(cmd: &str) -> Option<(Option<&str>, &str)> {
     lazy_static! {
         // Construct a regex that matches timed queue commands.
-        // E.g. `/1930` for 19:30 or `/645` for 6:45.
-        static ref RE: Regex = Regex::new(r"^\d{3,4}$").unwrap();
+        // E.g. `/1930` for 19:30, `/645` for 6:45 or `/fri1930` for Friday 19:30.
+        static ref RE: Regex = Regex::new(r"^(mon|tue|wed|thu|fri|sat|sun)?(\d{3,4})$").unwrap();
     }
 
-    RE.is_match(cmd)
+    let caps = RE.captures(cmd)?;
+    let weekday = caps.get(1).map(|x| x.as_str());
+    let time = caps.get(2)?.as_str();
+
+    Some((weekday, time))
+}
+
+/// Finds the map name matching `cmd`, with or without its prefix, e.g.
+/// `mirage` or `de_mirage`.
+fn find_map_name(cmd: &str) -> Option<&'static str> {
+    MAP_NAMES
+        .iter()
+        .copied()
+        .find(|map| *map == cmd || map.split_once('_').is_some_and(|(_, name)| name == cmd))
 }
 
 fn matches_cs_map_name(cmd: &str) -> bool {
@@ -156,6 +442,21 @@ fn parse_time_arg(s: &str) -> Result<NaiveTime, chrono::ParseError> {
     NaiveTime::parse_from_str(&timed_queue, "%H%M")
 }
 
+fn parse_day_arg(s: &str) -> Option<QueueDay> {
+    if s.eq_ignore_ascii_case("tomorrow") {
+        return Some(QueueDay::Tomorrow);
+    }
+
+    if let Ok(weekday) = s.parse::<Weekday>() {
+        return Some(QueueDay::Weekday(weekday));
+    }
+
+    ["%Y-%m-%d", "%d.%m.%Y"]
+        .iter()
+        .find_map(|fmt| NaiveDate::parse_from_str(s, fmt).ok())
+        .map(QueueDay::Date)
+}
+
 fn parse_username_arg(s: String) -> Option<Username> {
     lazy_static! {
         // Construct a regex that matches `@username`.
@@ -168,6 +469,332 @@ fn parse_username_arg(s: String) -> Option<Username> {
     Some(Username::new(username.to_string()))
 }
 
+fn parse_size_arg(s: &str) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let size: usize = s.parse()?;
+
+    if size == 0 || size > MAX_QUEUE_SIZE {
+        return Err(format!("Queue size must be between 1 and {MAX_QUEUE_SIZE}").into());
+    }
+
+    Ok(size)
+}
+
+/// Parses a timed queue argument, e.g. `1930` or `fri1930`.
+fn parse_queue_time_arg(s: &str) -> Result<QueueTime, Box<dyn std::error::Error + Send + Sync>> {
+    let (weekday, time) = matches_timed_queue(s).ok_or(format!("Invalid queue time {s}"))?;
+    let weekday = weekday.map(|w| w.parse::<Weekday>()).transpose()?;
+
+    Ok(QueueTime {
+        time: parse_time_arg(time)?,
+        day: weekday.map(QueueDay::Weekday).unwrap_or_default(),
+    })
+}
+
+fn parse_minutes_arg(s: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let minutes: i64 = s.parse()?;
+
+    if minutes <= 0 || minutes > MAX_EXTEND_MINUTES {
+        return Err(format!("Minutes must be between 1 and {MAX_EXTEND_MINUTES}").into());
+    }
+
+    Ok(minutes)
+}
+
+fn parse_move_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let args = args.unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let from = parse_queue_time_arg(args.next().ok_or("Missing queue time")?)?;
+    let to = parse_queue_time_arg(args.next().ok_or("Missing new queue time")?)?;
+    let merge = args.next() == Some("merge");
+
+    Ok(Command::Move { from, to, merge })
+}
+
+fn parse_kick_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let args = args.unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let user = args
+        .next()
+        .and_then(|arg| parse_username_arg(arg.to_string()))
+        .ok_or("Missing @username")?;
+    let time = args.next().map(parse_queue_time_arg).transpose()?;
+
+    Ok(Command::Kick { user, time })
+}
+
+/// Parses a time range, e.g. `1800-2200`.
+fn parse_window_arg(
+    s: &str,
+) -> Result<(NaiveTime, NaiveTime), Box<dyn std::error::Error + Send + Sync>> {
+    let (from, to) = s.split_once('-').ok_or("Invalid time range")?;
+
+    Ok((parse_time_arg(from)?, parse_time_arg(to)?))
+}
+
+fn parse_subscribe_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let mut min_players = None;
+    let mut window = None;
+    let mut dm = false;
+
+    for arg in args.iter().flat_map(|args| args.split_whitespace()) {
+        if arg == "dm" {
+            dm = true;
+        } else if arg.contains('-') {
+            window = Some(parse_window_arg(arg)?);
+        } else {
+            min_players = Some(parse_size_arg(arg)?);
+        }
+    }
+
+    Ok(Command::Subscribe {
+        min_players,
+        window,
+        dm,
+    })
+}
+
+/// Arguments accepted by queue commands, e.g. `@username size=2 tomorrow`.
+#[derive(Default)]
+struct QueueArgs {
+    for_user: Option<Username>,
+    size: Option<usize>,
+    day: Option<QueueDay>,
+    mode: JoinMode,
+}
+
+fn parse_queue_args(
+    args: Option<String>,
+) -> Result<QueueArgs, Box<dyn std::error::Error + Send + Sync>> {
+    let mut queue_args = QueueArgs::default();
+
+    for arg in args.iter().flat_map(|args| args.split_whitespace()) {
+        if let Some(size) = arg.strip_prefix("size=") {
+            queue_args.size = Some(parse_size_arg(size)?);
+        } else if arg == "reserve" {
+            queue_args.mode = JoinMode::Reserve;
+        } else if arg == "maybe" {
+            queue_args.mode = JoinMode::Maybe;
+        } else if arg == "yes" {
+            queue_args.mode = JoinMode::Yes;
+        } else if let Some(day) = parse_day_arg(arg) {
+            queue_args.day = Some(day);
+        } else if let Some(for_user) = parse_username_arg(arg.to_string()) {
+            queue_args.for_user = Some(for_user);
+        }
+    }
+
+    Ok(queue_args)
+}
+
+/// Parses a set of weekdays, e.g. `tue,thu`, `weekdays` or `daily`.
+fn parse_weekdays_arg(s: &str) -> Result<Vec<Weekday>, chrono::ParseWeekdayError> {
+    let all = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    match s {
+        "daily" => Ok(all.to_vec()),
+        "weekdays" => Ok(all[..5].to_vec()),
+        "weekends" => Ok(all[5..].to_vec()),
+        _ => s.split(',').map(|weekday| weekday.parse()).collect(),
+    }
+}
+
+fn parse_auto_args(
+    args: Option<String>,
+) -> Result<AutoCmd, Box<dyn std::error::Error + Send + Sync>> {
+    let args = args.unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let subcmd = args.next().unwrap_or("ls");
+
+    let cmd = match subcmd {
+        "ls" | "list" => AutoCmd::List,
+        "add" => {
+            let weekdays = parse_weekdays_arg(args.next().ok_or("Missing weekdays")?)?;
+            let (from, to) = parse_window_arg(args.next().ok_or("Missing time range")?)?;
+
+            AutoCmd::Add { weekdays, from, to }
+        }
+        "rm" | "del" | "delete" => {
+            let id = args.next().ok_or("Missing rule id")?;
+
+            AutoCmd::Remove {
+                id: id.trim_start_matches('#').parse()?,
+            }
+        }
+        _ => return Err(format!("Unknown subcommand {subcmd}").into()),
+    };
+
+    Ok(cmd)
+}
+
+fn parse_avail_args(
+    args: Option<String>,
+) -> Result<AvailCmd, Box<dyn std::error::Error + Send + Sync>> {
+    let args = args.unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let cmd = match args.next() {
+        None | Some("ls" | "list") => AvailCmd::List,
+        Some("rm" | "del" | "delete") => AvailCmd::Remove,
+        Some(range) => {
+            let (from, to) = parse_window_arg(range)?;
+            if from == to {
+                return Err("Empty time range".into());
+            }
+
+            let day = args
+                .next()
+                .map(|arg| parse_day_arg(arg).ok_or(format!("Invalid day {arg}")))
+                .transpose()?;
+
+            AvailCmd::Add {
+                from,
+                to,
+                day: day.unwrap_or_default(),
+            }
+        }
+    };
+
+    Ok(cmd)
+}
+
+fn parse_teams_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let mut time = None;
+    let mut rating = TeamRating::default();
+    let mut reroll = false;
+
+    for arg in args.iter().flat_map(|args| args.split_whitespace()) {
+        match arg {
+            "reroll" | "again" => reroll = true,
+            "premier" => rating = TeamRating::Premier,
+            "leetify" => rating = TeamRating::Leetify,
+            _ => time = Some(parse_queue_time_arg(arg)?),
+        }
+    }
+
+    Ok(Command::Teams {
+        time,
+        rating,
+        reroll,
+    })
+}
+
+fn parse_draft_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let mut time = None;
+    let mut captains = vec![];
+
+    for arg in args.iter().flat_map(|args| args.split_whitespace()) {
+        if let Some(captain) = parse_username_arg(arg.to_string()) {
+            captains.push(captain);
+        } else {
+            time = Some(parse_queue_time_arg(arg)?);
+        }
+    }
+
+    if !matches!(captains.len(), 0 | 2) {
+        return Err("Give either no captains or two captains".into());
+    }
+
+    Ok(Command::Draft { time, captains })
+}
+
+fn parse_veto_args(
+    args: Option<String>,
+) -> Result<Command, Box<dyn std::error::Error + Send + Sync>> {
+    let mut best_of = 1;
+    let mut sides = vec![];
+
+    for arg in args.iter().flat_map(|args| args.split_whitespace()) {
+        match arg {
+            "bo1" => best_of = 1,
+            "bo3" => best_of = 3,
+            _ => {
+                let side =
+                    parse_username_arg(arg.to_string()).ok_or(format!("Unknown argument {arg}"))?;
+                sides.push(side);
+            }
+        }
+    }
+
+    if !matches!(sides.len(), 0 | 2) {
+        return Err("Give either no sides or two sides".into());
+    }
+
+    Ok(Command::Veto { best_of, sides })
+}
+
+fn parse_recurring_args(
+    args: Option<String>,
+) -> Result<RecurringCmd, Box<dyn std::error::Error + Send + Sync>> {
+    let args = args.unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let subcmd = args.next().unwrap_or("ls");
+
+    let parse_id = |id: Option<&str>| -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        let id = id.ok_or("Missing recurring queue id")?;
+        Ok(id.trim_start_matches('#').parse()?)
+    };
+
+    let cmd = match subcmd {
+        "ls" | "list" => RecurringCmd::List,
+        "add" => {
+            let weekdays = parse_weekdays_arg(args.next().ok_or("Missing weekdays")?)?;
+            let time = parse_time_arg(args.next().ok_or("Missing time")?)?;
+
+            let mut players = vec![];
+            let mut size = None;
+
+            for arg in args {
+                if let Some(s) = arg.strip_prefix("size=") {
+                    size = Some(parse_size_arg(s)?);
+                } else if let Some(username) = parse_username_arg(arg.to_string()) {
+                    players.push(username);
+                }
+            }
+
+            RecurringCmd::Add {
+                weekdays,
+                time,
+                players,
+                size,
+            }
+        }
+        "pause" => RecurringCmd::Pause {
+            id: parse_id(args.next())?,
+        },
+        "resume" => RecurringCmd::Resume {
+            id: parse_id(args.next())?,
+        },
+        "rm" | "del" | "delete" => RecurringCmd::Remove {
+            id: parse_id(args.next())?,
+        },
+        _ => return Err(format!("Unknown subcommand {subcmd}").into()),
+    };
+
+    Ok(cmd)
+}
+
 pub fn parse_cmd(text: &str) -> Result<Option<Command>, Box<dyn std::error::Error + Send + Sync>> {
     let text = text.trim();
 
@@ -179,7 +806,9 @@ pub fn parse_cmd(text: &str) -> Result<Option<Command>, Box<dyn std::error::Erro
         match cmd.as_str() {
             "help" | "info" | "version" | "v" | "start" => Some(Command::Help),
             "rm" => Some(Command::RemoveAll),
+            "ready" | "r" => Some(Command::Ready),
             "ls" | "list" | "count" => Some(Command::List),
+            "recurring" | "weekly" => Some(Command::Recurring(parse_recurring_args(args)?)),
             "statistics" | "stats" => {
                 let for_user = args.and_then(parse_username_arg);
 
@@ -222,66 +851,94 @@ pub fn parse_cmd(text: &str) -> Result<Option<Command>, Box<dyn std::error::Erro
                 rank_type: "wingman".to_string(),
             }),
 
-            "cs_office" | "office" => Some(Command::HallOfFame {
-                rank_type: "cs_office".to_string(),
-            }),
-            "cs_italy" | "italy" => Some(Command::HallOfFame {
-                rank_type: "cs_italy".to_string(),
-            }),
-            "de_mirage" | "mirage" => Some(Command::HallOfFame {
-                rank_type: "de_mirage".to_string(),
-            }),
-            "de_overpass" | "overpass" => Some(Command::HallOfFame {
-                rank_type: "de_overpass".to_string(),
-            }),
-            "de_inferno" | "inferno" => Some(Command::HallOfFame {
-                rank_type: "de_inferno".to_string(),
-            }),
-            "de_nuke" | "nuke" => Some(Command::HallOfFame {
-                rank_type: "de_nuke".to_string(),
-            }),
-            "de_train" | "train" => Some(Command::HallOfFame {
-                rank_type: "de_train".to_string(),
-            }),
-            "de_vertigo" | "vertigo" => Some(Command::HallOfFame {
-                rank_type: "de_vertigo".to_string(),
-            }),
-            "de_dust2" | "dust2" => Some(Command::HallOfFame {
-                rank_type: "de_dust2".to_string(),
-            }),
-            "de_cache" | "cache" => Some(Command::HallOfFame {
-                rank_type: "de_cache".to_string(),
-            }),
-            "de_ancient" | "ancient" => Some(Command::HallOfFame {
-                rank_type: "de_ancient".to_string(),
-            }),
-            "de_anubis" | "anubis" => Some(Command::HallOfFame {
-                rank_type: "de_anubis".to_string(),
-            }),
-
             "lastplayed" => {
                 let for_user = args.and_then(parse_username_arg);
 
                 Some(Command::LastPlayed { for_user })
             }
+            "queuesize" | "size" => {
+                let size = args.as_deref().map(parse_size_arg).transpose()?;
+
+                Some(Command::QueueSize { size })
+            }
+            "move" | "mv" => Some(parse_move_args(args)?),
+            "kick" => Some(parse_kick_args(args)?),
+            "clear" => {
+                let time = args.as_deref().map(parse_queue_time_arg).transpose()?;
+
+                Some(Command::Clear { time })
+            }
+            "clearall" => Some(Command::ClearAll),
+            "subscribe" | "sub" => Some(parse_subscribe_args(args)?),
+            "unsubscribe" | "unsub" => Some(Command::Unsubscribe),
+            "auto" => Some(Command::Auto(parse_auto_args(args)?)),
+            "avail" | "available" => Some(Command::Avail(parse_avail_args(args)?)),
+            "teams" => Some(parse_teams_args(args)?),
+            "draft" => Some(parse_draft_args(args)?),
+            "veto" => Some(parse_veto_args(args)?),
+            "reliability" | "noshows" => Some(Command::Reliability),
+            "queuestats" => {
+                let csv = match args.as_deref().map(str::trim) {
+                    None | Some("") => false,
+                    Some("csv") => true,
+                    Some(arg) => return Err(format!("Unknown argument {arg}").into()),
+                };
+
+                Some(Command::QueueStats { csv })
+            }
+            "extend" => {
+                let minutes = args.as_deref().map(parse_minutes_arg).transpose()?;
+
+                Some(Command::Extend { minutes })
+            }
             "add" | "instant" | "heti" | "kynär" | "kynäri" => {
-                let for_user = args.and_then(parse_username_arg);
+                let QueueArgs {
+                    for_user,
+                    size,
+                    day,
+                    mode,
+                } = parse_queue_args(args)?;
+
+                // The instant queue starts right away, so a day makes no sense.
+                if day.is_some() {
+                    return Err(
+                        "The instant queue doesn't take a day, use e.g. /1930 tomorrow".into(),
+                    );
+                }
 
                 Some(Command::AddRemove {
                     time: None,
                     for_user,
+                    size,
+                    mode,
                 })
             }
             _ => {
                 // Didn't match any of our normal commands, check for timed
                 // queue command match.
-                if matches_timed_queue(&cmd) {
-                    let parsed_time = parse_time_arg(&cmd)?;
-                    let for_user = args.and_then(parse_username_arg);
+                if let Some((weekday, time)) = matches_timed_queue(&cmd) {
+                    let parsed_time = parse_time_arg(time)?;
+                    let QueueArgs {
+                        for_user,
+                        size,
+                        day,
+                        mode,
+                    } = parse_queue_args(args)?;
+                    let weekday = weekday.map(|w| w.parse::<Weekday>()).transpose()?;
+                    let day = day.or(weekday.map(QueueDay::Weekday)).unwrap_or_default();
 
                     Some(Command::AddRemove {
-                        time: Some(parsed_time),
+                        time: Some(QueueTime {
+                            time: parsed_time,
+                            day,
+                        }),
                         for_user,
+                        size,
+                        mode,
+                    })
+                } else if let Some(map) = find_map_name(&cmd) {
+                    Some(Command::HallOfFame {
+                        rank_type: map.to_string(),
                     })
                 } else if matches_cs_map_name(&cmd) {
                     Some(Command::HallOfFame {
@@ -300,3 +957,108 @@ pub fn parse_cmd(text: &str) -> Result<Option<Command>, Box<dyn std::error::Erro
 
     Ok(cmd_result)
 }
+
+/// Parses callback data of an inline keyboard button press.
+pub fn parse_callback(data: &str) -> Option<CallbackCmd> {
+    let (action, args) = data.split_once(':')?;
+    let queue_id = QueueId::new(args.to_string());
+
+    match action {
+        "join" => Some(CallbackCmd::Join { queue_id }),
+        "maybe" => Some(CallbackCmd::Maybe { queue_id }),
+        "leave" => Some(CallbackCmd::Leave { queue_id }),
+        "ready" => Some(CallbackCmd::Ready { queue_id }),
+        "pick" => {
+            let (index, queue_id) = args.split_once(':')?;
+
+            Some(CallbackCmd::Pick {
+                queue_id: QueueId::new(queue_id.to_string()),
+                index: index.parse().ok()?,
+            })
+        }
+        "veto" => {
+            let (map, queue_id) = args.split_once(':')?;
+
+            Some(CallbackCmd::Veto {
+                queue_id: QueueId::new(queue_id.to_string()),
+                map: map.parse().ok()?,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Tz> {
+        let t = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        chrono_tz::Europe::Helsinki.from_local_datetime(&t).unwrap()
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        parse_time_arg(s).unwrap()
+    }
+
+    #[test]
+    fn resolves_next_occurrence() {
+        // 2024-12-20 is a Friday.
+        let now = at("2024-12-20 18:00");
+
+        let today = QueueDay::Next.resolve(time("1930"), &now);
+        assert_eq!(today, Some(at("2024-12-20 19:30")));
+
+        let tomorrow = QueueDay::Next.resolve(time("1700"), &now);
+        assert_eq!(tomorrow, Some(at("2024-12-21 17:00")));
+
+        let tomorrow = QueueDay::Tomorrow.resolve(time("1930"), &now);
+        assert_eq!(tomorrow, Some(at("2024-12-21 19:30")));
+    }
+
+    #[test]
+    fn resolves_weekdays_and_dates() {
+        let now = at("2024-12-20 18:00");
+
+        let fri = QueueDay::Weekday(Weekday::Fri).resolve(time("1930"), &now);
+        assert_eq!(fri, Some(at("2024-12-20 19:30")));
+
+        let next_fri = QueueDay::Weekday(Weekday::Fri).resolve(time("1700"), &now);
+        assert_eq!(next_fri, Some(at("2024-12-27 17:00")));
+
+        let tue = QueueDay::Weekday(Weekday::Tue).resolve(time("1930"), &now);
+        assert_eq!(tue, Some(at("2024-12-24 19:30")));
+
+        let date = NaiveDate::from_ymd_opt(2024, 12, 19).unwrap();
+        assert_eq!(QueueDay::Date(date).resolve(time("1930"), &now), None);
+    }
+
+    #[test]
+    fn ignores_free_text_in_queue_args() {
+        let args = parse_queue_args(Some(String::from("size=2 tomorrow"))).unwrap();
+        assert_eq!(args.size, Some(2));
+        assert_eq!(args.day, Some(QueueDay::Tomorrow));
+
+        let args = parse_queue_args(Some(String::from("see you there"))).unwrap();
+        assert_eq!(args.size, None);
+        assert_eq!(args.for_user, None);
+    }
+
+    #[test]
+    fn parses_timed_queue_commands() {
+        assert_eq!(matches_timed_queue("1930"), Some((None, "1930")));
+        assert_eq!(matches_timed_queue("fri645"), Some((Some("fri"), "645")));
+        assert_eq!(matches_timed_queue("friday1930"), None);
+        assert_eq!(parse_day_arg("tomorrow"), Some(QueueDay::Tomorrow));
+        assert_eq!(
+            parse_day_arg("24.12.2024"),
+            Some(QueueDay::Date(
+                NaiveDate::from_ymd_opt(2024, 12, 24).unwrap()
+            ))
+        );
+
+        // Days only apply to timed queues.
+        assert!(parse_cmd("/1930 tomorrow").unwrap().is_some());
+        assert!(parse_cmd("/add tomorrow").is_err());
+    }
+}
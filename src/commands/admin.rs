@@ -0,0 +1,177 @@
+use chrono::Utc;
+use chrono_tz::Tz;
+use teloxide::{prelude::*, types::ChatId, utils::html::escape, Bot};
+
+use crate::{
+    command::QueueTime,
+    commands::{
+        queue::{close_status_msg, send_rm_player_results},
+        Context,
+    },
+    settings::Settings,
+    state::Queue,
+    types::{Player, QueueId},
+    util::send_msg,
+};
+
+/// Returns whether player is allowed to moderate queues in given chat, i.e.
+/// is a chat administrator or listed as an admin in settings.
+pub async fn is_admin(bot: &Bot, settings: &Settings, chat_id: ChatId, player: &Player) -> bool {
+    let Some(user_id) = player.id else {
+        return false;
+    };
+
+    if settings.queue.admins.contains(&user_id) {
+        return true;
+    }
+
+    match bot.get_chat_member(chat_id, user_id).await {
+        Ok(member) => member.is_privileged(),
+        Err(error) => {
+            eprintln!("Error while fetching Telegram chat member: {}", error);
+            false
+        }
+    }
+}
+
+/// Returns whether `caller` added `player` to the queue.
+fn added(queue: &Queue, caller: &Player, player: &Player) -> bool {
+    caller.id.is_some() && queue.added_by(player) == caller.id
+}
+
+/// Returns whether `caller` is allowed to remove `player` from a queue, i.e.
+/// is the player themselves, added the player or is an admin.
+pub async fn can_remove(
+    bot: &Bot,
+    settings: &Settings,
+    chat_id: ChatId,
+    queue: &Queue,
+    caller: &Player,
+    player: &Player,
+) -> bool {
+    if caller == player || added(queue, caller, player) {
+        return true;
+    }
+
+    is_admin(bot, settings, chat_id, caller).await
+}
+
+/// Resolves the queue referred to by an optional queue time, defaulting to
+/// the instant queue.
+pub fn resolve_queue_id(time: Option<QueueTime>, tz: &Tz) -> Option<QueueId> {
+    let Some(QueueTime { time, day }) = time else {
+        return Some(QueueId::instant());
+    };
+
+    let now = Utc::now().with_timezone(tz);
+    day.resolve(time, &now).map(|t| QueueId::timed(&t))
+}
+
+/// Removes a player from given timed queue, or from all queues. Admins can
+/// remove anyone, others only players they added.
+pub async fn kick(ctx: Context<'_>, caller: &Player, player: Player, time: Option<QueueTime>) {
+    let queue_id = match time {
+        Some(time) => {
+            let Some(queue_id) = resolve_queue_id(Some(time), ctx.tz) else {
+                let text = "Can't kick from a queue in the past.";
+                send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+                return;
+            };
+
+            Some(queue_id)
+        }
+        None => None,
+    };
+
+    let admin = *caller == player || is_admin(ctx.bot, ctx.settings, ctx.chat_id, caller).await;
+
+    let affected_queues = ctx
+        .sc
+        .update(|state| {
+            let (state, affected) = state.rm_player_where(&ctx.chat_id, &player, |id, queue| {
+                queue_id.as_ref().is_none_or(|queue_id| queue_id == id)
+                    && (admin || added(queue, caller, &player))
+            });
+
+            (!affected.is_empty()).then_some((state, affected))
+        })
+        .await;
+
+    let Some(affected_queues) = affected_queues else {
+        let text = format!(
+            "{} isn't in any queue you can remove them from.",
+            escape(&player.to_string())
+        );
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    send_rm_player_results(ctx, &player, affected_queues).await;
+}
+
+/// Removes given timed queue, or the instant queue. Only for admins.
+pub async fn clear(ctx: Context<'_>, caller: &Player, time: Option<QueueTime>) {
+    if !is_admin(ctx.bot, ctx.settings, ctx.chat_id, caller).await {
+        let text = "Only chat admins can clear queues.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    }
+
+    let Some(queue_id) = resolve_queue_id(time, ctx.tz) else {
+        let text = "Can't clear a queue in the past.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    };
+
+    let removed_queue = ctx
+        .sc
+        .update(|state| {
+            let (state, queue) = state.rm_chat_queue(&ctx.chat_id, &queue_id);
+            Some((state, queue?))
+        })
+        .await;
+
+    let Some(queue) = removed_queue else {
+        let text = format!("No {} queue to clear.", queue_id);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    let text = format!(
+        "{} queue cleared by {}.",
+        queue_id,
+        escape(&caller.to_string())
+    );
+    close_status_msg(ctx.bot, &ctx.chat_id, &queue, &text).await;
+}
+
+/// Removes all queues of the chat. Only for admins.
+pub async fn clear_all(ctx: Context<'_>, caller: &Player) {
+    if !is_admin(ctx.bot, ctx.settings, ctx.chat_id, caller).await {
+        let text = "Only chat admins can clear queues.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    }
+
+    let cleared = ctx
+        .sc
+        .update(|state| {
+            let (state, queues) = state.clear_chat_queues(&ctx.chat_id);
+            (!queues.is_empty()).then_some((state, queues))
+        })
+        .await;
+
+    let Some(queues) = cleared else {
+        send_msg(ctx.bot, &ctx.chat_id, "No active queues.", false).await;
+        return;
+    };
+
+    for (queue_id, queue) in queues {
+        let text = format!(
+            "{} queue cleared by {}.",
+            queue_id,
+            escape(&caller.to_string())
+        );
+        close_status_msg(ctx.bot, &ctx.chat_id, &queue, &text).await;
+    }
+}
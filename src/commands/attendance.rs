@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use teloxide::{types::ChatId, utils::html::escape, Bot};
+
+use crate::{
+    commands::stats::index_to_pos,
+    services::leetify::played_together,
+    settings::Settings,
+    state::{Attendance, QueueRecord, State, MAX_ATTENDANCE_CHECKS},
+    state_container::StateContainer,
+    types::{Player, Username},
+    util::{fmt_players, send_msg},
+};
+
+/// Hours between attendance checks of a queue whose games couldn't be
+/// checked yet.
+const ATTENDANCE_RETRY_HOURS: i64 = 6;
+
+/// Leetify only lists a player's recent games, so older queues can't be
+/// checked.
+const MAX_ATTENDANCE_CHECK_AGE_DAYS: i64 = 7;
+
+/// Returns when attendance of a finished queue can be checked next, or None
+/// if it doesn't need or can't be checked.
+pub fn attendance_check_due(settings: &Settings, record: &QueueRecord) -> Option<DateTime<Utc>> {
+    let hours = settings.queue.no_show_check_hours;
+
+    if hours <= 0
+        || !record.filled
+        || record.attendance.is_some()
+        || record.attendance_checks_left == 0
+        || record.start < Utc::now() - Duration::days(MAX_ATTENDANCE_CHECK_AGE_DAYS)
+    {
+        return None;
+    }
+
+    let retries = MAX_ATTENDANCE_CHECKS.saturating_sub(record.attendance_checks_left);
+    Some(record.start + Duration::hours(hours + ATTENDANCE_RETRY_HOURS * retries as i64))
+}
+
+/// Checks which players of a played queue finished a Leetify game together
+/// within the check window, records it and posts the result. Nothing is
+/// recorded if nobody's games could be checked, so that the check is retried.
+async fn check_queue_attendance(
+    sc: &StateContainer,
+    settings: &Settings,
+    bot: &Bot,
+    chat_id: &ChatId,
+    record: &QueueRecord,
+) {
+    let usernames: Vec<Username> = record
+        .lineup
+        .iter()
+        .map(|player| settings.players.mapping_username(player))
+        .collect();
+
+    let to = record.start + Duration::hours(settings.queue.no_show_check_hours);
+    let played = played_together(settings, &usernames, record.start, to).await;
+
+    let mut attendance = Attendance::default();
+    for (player, played) in record.lineup.iter().zip(played) {
+        match played {
+            Some(true) => attendance.showed_up.push(player.clone()),
+            Some(false) => attendance.no_shows.push(player.clone()),
+            None => {}
+        }
+    }
+
+    let showed_up = attendance.showed_up.len();
+    let checked = showed_up + attendance.no_shows.len();
+    let no_shows = fmt_players(&attendance.no_shows, false);
+
+    if checked == 0 {
+        return;
+    }
+
+    sc.update(|state| {
+        let state = state.set_queue_attendance(
+            chat_id,
+            &record.queue_id,
+            record.finished_at,
+            attendance,
+        )?;
+        Some((state, ()))
+    })
+    .await;
+
+    let no_shows = if no_shows.is_empty() {
+        String::new()
+    } else {
+        format!(" No-shows: {}.", no_shows)
+    };
+
+    let text = format!(
+        "{} queue: {}/{} showed up.{}",
+        record.queue_id, showed_up, checked, no_shows
+    );
+    send_msg(bot, chat_id, &text, false).await;
+}
+
+/// Checks attendance of played queues whose check window has passed. Checks
+/// run in the background, as fetching games can take a while.
+pub async fn check_due_attendance(sc: &StateContainer, settings: &Settings, bot: &Bot) {
+    let state = sc.read().await;
+    let now = Utc::now();
+
+    for (chat_id, chat) in &state.chats {
+        for record in &chat.history {
+            if attendance_check_due(settings, record).is_none_or(|t| t > now) {
+                continue;
+            }
+
+            let claimed = sc
+                .update(|state| {
+                    let state = state.claim_attendance_check(
+                        chat_id,
+                        &record.queue_id,
+                        record.finished_at,
+                    )?;
+                    Some((state, ()))
+                })
+                .await;
+
+            if claimed.is_none() {
+                continue;
+            }
+
+            let sc = sc.clone();
+            let settings = settings.clone();
+            let bot = bot.clone();
+            let chat_id = *chat_id;
+            let record = record.clone();
+
+            tokio::spawn(async move {
+                check_queue_attendance(&sc, &settings, &bot, &chat_id, &record).await;
+            });
+        }
+    }
+}
+
+/// Ranks players by how often they showed up to play queues they were in
+/// the lineup of.
+pub fn reliability(state: &State, chat_id: ChatId) -> String {
+    let attendances = state
+        .chats
+        .get(&chat_id)
+        .into_iter()
+        .flat_map(|chat| &chat.history)
+        .filter_map(|record| record.attendance.as_ref());
+
+    let mut counts: HashMap<&Player, (usize, usize)> = HashMap::new();
+    for attendance in attendances {
+        for player in &attendance.showed_up {
+            let (showed_up, checked) = counts.entry(player).or_default();
+            *showed_up += 1;
+            *checked += 1;
+        }
+
+        for player in &attendance.no_shows {
+            counts.entry(player).or_default().1 += 1;
+        }
+    }
+
+    if counts.is_empty() {
+        return String::from("No played queues have been checked for no-shows yet.");
+    }
+
+    let mut counts: Vec<(&Player, (usize, usize))> = counts.into_iter().collect();
+    counts.sort_by(|(a, (a_showed, a_checked)), (b, (b_showed, b_checked))| {
+        // Compare showed up / checked ratios without floating point.
+        (b_showed * a_checked)
+            .cmp(&(a_showed * b_checked))
+            .then(b_checked.cmp(a_checked))
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+
+    let list = counts
+        .into_iter()
+        .enumerate()
+        .map(|(index, (player, (showed_up, checked)))| {
+            format!(
+                "{}: {} {}% ({}/{})",
+                index_to_pos(index),
+                escape(&player.to_string()),
+                showed_up * 100 / checked,
+                showed_up,
+                checked
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!("Reliability, or how often players showed up:\n\n{}", list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{
+            test_util::{add, players, CHAT_ID},
+            JoinMode,
+        },
+        types::QueueId,
+    };
+
+    #[test]
+    fn ranks_players_by_attendance() {
+        let players = players(2);
+        let queue_id = QueueId::instant();
+        let mut state = State::default();
+
+        for no_shows in [vec![], vec![players[1].clone()]] {
+            let mut next = state.clone();
+            for player in &players {
+                (next, ..) = add(&next, &queue_id, Some(2), player, JoinMode::Yes);
+            }
+
+            let (next, _) = next.fire_chat_queue(&CHAT_ID, &queue_id);
+            let record = next.chats[&CHAT_ID].history.last().unwrap().clone();
+
+            let attendance = Attendance {
+                showed_up: vec![players[0].clone()],
+                no_shows,
+            };
+            state = next
+                .set_queue_attendance(&CHAT_ID, &queue_id, record.finished_at, attendance)
+                .unwrap();
+        }
+
+        assert_eq!(state.chats[&CHAT_ID].history.len(), 2);
+
+        assert_eq!(
+            reliability(&state, CHAT_ID),
+            "Reliability, or how often players showed up:\n\n🥇: p1 100% (2/2)\n🥈: p2 0% (0/1)"
+        );
+    }
+
+    #[test]
+    fn attendance_checks_are_bounded() {
+        let players = players(2);
+        let queue_id = QueueId::instant();
+
+        let mut state = State::default();
+        for player in &players {
+            (state, ..) = add(&state, &queue_id, Some(2), player, JoinMode::Yes);
+        }
+        let (mut state, _) = state.fire_chat_queue(&CHAT_ID, &queue_id);
+        let finished_at = state.chats[&CHAT_ID].history[0].finished_at;
+
+        for _ in 0..MAX_ATTENDANCE_CHECKS {
+            state = state
+                .claim_attendance_check(&CHAT_ID, &queue_id, finished_at)
+                .unwrap();
+        }
+
+        assert!(state
+            .claim_attendance_check(&CHAT_ID, &queue_id, finished_at)
+            .is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{command::AutoCmd, commands::Context, state::AutoAddRule, types::Player};
+
+fn fmt_auto_add_rule(rule: &AutoAddRule) -> String {
+    let weekdays = rule
+        .weekdays
+        .iter()
+        .map(|weekday| weekday.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!(
+        "#{} {} {}-{}",
+        rule.id,
+        weekdays,
+        rule.from.format("%H:%M"),
+        rule.to.format("%H:%M")
+    )
+}
+
+/// Lists or manages the player's availability rules.
+pub async fn auto(ctx: Context<'_>, player: Player, cmd: AutoCmd) -> String {
+    match cmd {
+        AutoCmd::List => {
+            let rules: Vec<String> = ctx.sc
+                .read()
+                .await
+                .chats
+                .get(&ctx.chat_id)
+                .map(|chat| {
+                    chat.auto_add_rules
+                        .iter()
+                        .filter(|rule| rule.player == player)
+                        .map(fmt_auto_add_rule)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if rules.is_empty() {
+                return String::from(
+                    "No availability rules. Add one with e.g. /auto add weekdays 1900-2100",
+                );
+            }
+
+            rules.join("\n")
+        }
+        AutoCmd::Add { weekdays, from, to } => {
+            ctx.sc.update(|state| {
+                let (state, rule) = state.add_auto_add_rule(&ctx.chat_id, player, weekdays, from, to);
+                let text = format!(
+                    "You'll be added to new queues matching {}. Leave them as usual if you can't make it.",
+                    fmt_auto_add_rule(&rule)
+                );
+                Some((state, text))
+            })
+            .await
+            .unwrap_or_default()
+        }
+        AutoCmd::Remove { id } => {
+            let removed = ctx.sc
+                .update(|state| Some((state.rm_auto_add_rule(&ctx.chat_id, &player, id)?, ())))
+                .await;
+
+            match removed {
+                Some(()) => format!("Availability rule #{} deleted.", id),
+                None => format!("You have no availability rule #{}.", id),
+            }
+        }
+    }
+}
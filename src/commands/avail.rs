@@ -0,0 +1,194 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use teloxide::utils::html::escape;
+
+use crate::{
+    command::{AvailCmd, QueueDay},
+    commands::{queue::show_queue_status, subscription::notify_subscribers, Context},
+    state::{Availability, State},
+    types::{Player, QueueId},
+    util::{fmt_players, mk_add_cmd, mk_players_str, send_msg},
+};
+
+/// Resolves a time range on given day to points in time. Ranges ending at or
+/// before their start time end on the next day.
+///
+/// Without an explicit day, the range is the next one that hasn't ended yet,
+/// so ranges already underway today are kept.
+fn resolve_range(
+    from: NaiveTime,
+    to: NaiveTime,
+    day: QueueDay,
+    now: &DateTime<Tz>,
+) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+    let length = if from < to {
+        to - from
+    } else {
+        to - from + Duration::days(1)
+    };
+
+    let start = match day {
+        QueueDay::Next => {
+            let end = QueueDay::Next.resolve(to, now)?;
+            let start = end.naive_local() - length;
+            now.timezone().from_local_datetime(&start).earliest()?
+        }
+        day => day.resolve(from, now)?,
+    };
+
+    Some((start, start + length))
+}
+
+fn fmt_availability(availability: &Availability, tz: &Tz) -> String {
+    format!(
+        "{} {}-{}",
+        escape(&availability.player.to_string()),
+        availability.from.with_timezone(tz).format("%a %H:%M"),
+        availability.to.with_timezone(tz).format("%H:%M")
+    )
+}
+
+/// Lists posted availability ranges ordered by start time like queues are,
+/// along with the best start time found so far.
+fn list_availability(ctx: Context<'_>, state: &State) -> String {
+    let Some(chat) = state.chats.get(&ctx.chat_id) else {
+        return String::from("Nobody has posted their availability yet.");
+    };
+
+    let now = Utc::now();
+    let mut ranges: Vec<&Availability> = chat
+        .availability
+        .iter()
+        .filter(|availability| availability.to > now)
+        .collect();
+
+    if ranges.is_empty() {
+        return String::from("Nobody has posted their availability yet.");
+    }
+
+    ranges.sort_by_key(|availability| availability.from);
+
+    let ranges = ranges
+        .into_iter()
+        .map(|availability| fmt_availability(availability, ctx.tz))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let size = chat.queue_size();
+    let length = Duration::minutes(ctx.settings.queue.match_minutes);
+    let best = chat
+        .best_availability_slot(size, length, now)
+        .map(|(t, players)| {
+            format!(
+                "\nBest time so far: {} with {}/{} players ({}).",
+                t.with_timezone(ctx.tz).format("%a %H:%M"),
+                players.len(),
+                size,
+                fmt_players(&players, false)
+            )
+        })
+        .unwrap_or_default();
+
+    format!("Available:\n{}{}", ranges, best)
+}
+
+/// Lists, posts or removes availability ranges. Once enough players'
+/// ranges overlap, a timed queue is opened at the earliest such time with the
+/// overlapping players in it.
+pub async fn avail(ctx: Context<'_>, player: Player, cmd: AvailCmd) {
+    let (from, to, day) = match cmd {
+        AvailCmd::List => {
+            let text = list_availability(ctx, &ctx.sc.read().await);
+            send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+            return;
+        }
+        AvailCmd::Remove => {
+            let removed = ctx
+                .sc
+                .update(|state| Some((state.rm_availability(&ctx.chat_id, &player)?, ())))
+                .await;
+            let text = match removed {
+                Some(()) => "Your availability was removed.",
+                None => "You haven't posted your availability.",
+            };
+            send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+            return;
+        }
+        AvailCmd::Add { from, to, day } => (from, to, day),
+    };
+
+    let now = Utc::now().with_timezone(ctx.tz);
+
+    let Some((from, to)) = resolve_range(from, to, day, &now) else {
+        let text = "Can't post availability in the past.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    };
+
+    let availability = Availability {
+        player,
+        from: from.with_timezone(&Utc),
+        to: to.with_timezone(&Utc),
+    };
+    let length = Duration::minutes(ctx.settings.queue.match_minutes);
+
+    // Open a queue in the same update as posting, so that the overlap is
+    // found from current availability.
+    let opened = ctx
+        .sc
+        .update(|state| {
+            let state = state.set_availability(&ctx.chat_id, availability, Utc::now());
+
+            let chat = &state.chats[&ctx.chat_id];
+            let size = chat.queue_size();
+            let slot = chat
+                .best_availability_slot(size, length, Utc::now())
+                .filter(|(_, players)| players.len() >= size);
+
+            let Some((t, players)) = slot else {
+                return Some((state, None));
+            };
+
+            let t = t.with_timezone(ctx.tz);
+            let queue_id = QueueId::timed(&t);
+            let add_cmd = mk_add_cmd(&t, &now);
+
+            let (state, queue) = state.open_availability_queue(
+                &ctx.chat_id,
+                &queue_id,
+                add_cmd,
+                t.with_timezone(&Utc),
+                &players,
+            );
+            Some((state, Some((t, queue_id, queue, players))))
+        })
+        .await
+        .flatten();
+
+    let Some((t, queue_id, queue, players)) = opened else {
+        let text = list_availability(ctx, &ctx.sc.read().await);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    let text = format!(
+        "{} players are available at {}!\n{} queue opened.\n{}.\nUse {} to add/remove yourself from the queue!",
+        players.len(),
+        t.format("%H:%M"),
+        queue_id,
+        mk_players_str(&queue, true, false),
+        queue.add_cmd
+    );
+    show_queue_status(
+        ctx.bot,
+        ctx.sc,
+        &ctx.chat_id,
+        &queue_id,
+        &queue,
+        &text,
+        true,
+    )
+    .await;
+
+    notify_subscribers(ctx.bot, ctx.sc, &ctx.chat_id, ctx.tz, &queue_id).await;
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use chrono_tz::Tz;
+use teloxide::{
+    types::{ChatId, InputFile},
+    utils::html::escape,
+};
+
+use crate::{
+    commands::Context,
+    state::{QueueRecord, State},
+    types::Player,
+    util::{send_document, send_msg},
+};
+
+/// Number of entries shown in each /queuestats top list.
+const TOP_N: usize = 5;
+
+/// Counts how often each player appears in given lists of players, and
+/// returns the most frequent players formatted for display.
+fn fmt_top_players<'a>(players: impl Iterator<Item = &'a Player>) -> String {
+    let mut counts: HashMap<&Player, usize> = HashMap::new();
+    for player in players {
+        *counts.entry(player).or_default() += 1;
+    }
+
+    let mut counts: Vec<(&Player, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| {
+        b_count
+            .cmp(a_count)
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+
+    if counts.is_empty() {
+        return String::from("nobody");
+    }
+
+    counts
+        .into_iter()
+        .take(TOP_N)
+        .map(|(player, count)| format!("{} ({})", escape(&player.to_string()), count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Returns the most popular start times of timed queues along with how many
+/// queues started at each time and how many of them filled.
+fn popular_start_times(history: &[QueueRecord], tz: &Tz) -> Vec<(String, usize, usize)> {
+    let mut times: HashMap<String, (usize, usize)> = HashMap::new();
+
+    for record in history.iter().filter(|r| !r.queue_id.is_instant_queue()) {
+        let time = record.start.with_timezone(tz).format("%H:%M").to_string();
+        let (queues, filled) = times.entry(time).or_default();
+        *queues += 1;
+        *filled += usize::from(record.filled);
+    }
+
+    let mut times: Vec<(String, usize, usize)> = times
+        .into_iter()
+        .map(|(time, (queues, filled))| (time, queues, filled))
+        .collect();
+    times.sort_by(|(a, a_queues, _), (b, b_queues, _)| b_queues.cmp(a_queues).then(a.cmp(b)));
+    times.truncate(TOP_N);
+
+    times
+}
+
+/// Summarizes the chat's queue history: how often queues fill, the most
+/// popular start times, who joins most and who most often leaves full queues.
+pub fn queue_stats(state: &State, chat_id: ChatId, tz: &Tz) -> String {
+    let history = state
+        .chats
+        .get(&chat_id)
+        .map(|chat| chat.history.as_slice())
+        .unwrap_or_default();
+
+    let Some(first) = history.first() else {
+        return String::from("No queues have finished yet.");
+    };
+
+    let filled = history.iter().filter(|record| record.filled).count();
+
+    let times = popular_start_times(history, tz)
+        .into_iter()
+        .map(|(time, queues, filled)| {
+            format!(
+                "{} ({} queues, {}% filled)",
+                time,
+                queues,
+                filled * 100 / queues
+            )
+        })
+        .collect::<Vec<String>>();
+    let times = if times.is_empty() {
+        String::from("none yet")
+    } else {
+        times.join(", ")
+    };
+
+    let joined = fmt_top_players(
+        history
+            .iter()
+            .flat_map(|record| record.lineup.iter().chain(&record.reserve)),
+    );
+    let left = fmt_top_players(
+        history
+            .iter()
+            .flat_map(|record| record.left_after_full.iter()),
+    );
+
+    format!(
+        "Queue stats since {}:\nFilled: {}/{} queues ({}%)\nPopular times: {}\nMost joined: {}\nMost left full queues: {}\nUse /queuestats csv to export the history.",
+        first.finished_at.with_timezone(tz).format("%Y-%m-%d"),
+        filled,
+        history.len(),
+        filled * 100 / history.len(),
+        times,
+        joined,
+        left
+    )
+}
+
+/// Quotes a CSV field if it contains characters that need quoting.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Formats the queue history as CSV, one finished queue per row. Players are
+/// separated by semicolons.
+fn history_csv(history: &[QueueRecord], tz: &Tz) -> String {
+    let fmt_players = |players: &[Player]| {
+        players
+            .iter()
+            .map(|player| player.to_string())
+            .collect::<Vec<String>>()
+            .join(";")
+    };
+
+    let mut csv = String::from("type,start,finished_at,filled,lineup,reserve,left_after_full\r\n");
+
+    for record in history {
+        let queue_type = if record.queue_id.is_instant_queue() {
+            "instant"
+        } else {
+            "timed"
+        };
+
+        let fields = [
+            queue_type.to_string(),
+            record
+                .start
+                .with_timezone(tz)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            record
+                .finished_at
+                .with_timezone(tz)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            record.filled.to_string(),
+            fmt_players(&record.lineup),
+            fmt_players(&record.reserve),
+            fmt_players(&record.left_after_full),
+        ];
+
+        let row = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        csv.push_str(&row);
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+/// Shows queue stats of the chat, or sends its queue history as a CSV file
+/// if `csv` is set.
+pub async fn queuestats(ctx: Context<'_>, state: State, csv: bool) {
+    let history = state
+        .chats
+        .get(&ctx.chat_id)
+        .map(|chat| chat.history.as_slice())
+        .unwrap_or_default();
+
+    if !csv || history.is_empty() {
+        let text = queue_stats(&state, ctx.chat_id, ctx.tz);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    }
+
+    let csv = history_csv(history, ctx.tz);
+    let document = InputFile::memory(csv.into_bytes()).file_name("queue_history.csv");
+    send_document(ctx.bot, &ctx.chat_id, document).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{QueueId, Username};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn exports_history_as_csv() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let t = Utc.with_ymd_and_hms(2024, 12, 24, 17, 30, 0).unwrap();
+        let player = |name: &str| Player::new(None, Username::new(String::from(name)));
+
+        let record = QueueRecord {
+            queue_id: QueueId::timed(&t.with_timezone(&tz)),
+            start: t,
+            finished_at: t,
+            lineup: vec![player("alice"), player("bob, \"the builder\"")],
+            reserve: vec![],
+            filled: true,
+            left_after_full: vec![player("carol")],
+            attendance: None,
+            attendance_checks_left: 0,
+            veto: None,
+        };
+
+        let csv = history_csv(&[record], &tz);
+
+        assert_eq!(
+            csv,
+            "type,start,finished_at,filled,lineup,reserve,left_after_full\r\n\
+             timed,2024-12-24 19:30,2024-12-24 19:30,true,\"alice;bob, \"\"the builder\"\"\",,carol\r\n"
+        );
+    }
+}
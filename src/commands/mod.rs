@@ -1,5 +1,29 @@
+use chrono_tz::Tz;
+use teloxide::{types::ChatId, Bot};
+
+use crate::{settings::Settings, state_container::StateContainer};
+
 pub mod activity;
+pub mod admin;
+pub mod attendance;
+pub mod auto_add;
+pub mod avail;
+pub mod history;
 pub mod queue;
+pub mod recurring;
 pub mod sahko;
 pub mod stats;
+pub mod subscription;
+pub mod teams;
+pub mod veto;
 pub mod weather;
+
+/// What command handlers need for acting on the chat a command came from.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub bot: &'a Bot,
+    pub settings: &'a Settings,
+    pub sc: &'a StateContainer,
+    pub tz: &'a Tz,
+    pub chat_id: ChatId,
+}
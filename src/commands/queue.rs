@@ -1,67 +1,411 @@
-use std::cmp::Ordering;
+use chrono::{DateTime, Duration, NaiveTime, Timelike, Utc};
+use teloxide::{types::ChatId, utils::html::escape, Bot};
 
-use chrono::{NaiveTime, Timelike, Utc};
-use chrono_tz::Tz;
-use teloxide::{types::ChatId, Bot};
+use std::collections::HashMap;
 
 use crate::{
-    state::{AddRemovePlayerOp, AddRemovePlayerResult, Queue, State},
+    command::{QueueDay, QueueTime},
+    commands::{
+        admin::can_remove, subscription::notify_subscribers, teams::start_queue_draft, Context,
+    },
+    settings::{MissedTimeouts, Settings},
+    state::{
+        AddRemovePlayerArgs, AddRemovePlayerOp, AddRemovePlayerResult, JoinMode, Queue, State,
+        DEFAULT_QUEUE_SIZE,
+    },
     state_container::StateContainer,
-    types::{QueueId, Username},
-    util::{fmt_naive_time, mk_players_str, mk_queue_status_msg, send_msg},
+    types::{Player, QueueId},
+    util::{
+        edit_msg, fmt_players, mk_add_cmd, mk_players_str, mk_queue_keyboard, mk_queue_status_msg,
+        mk_ready_check_str, send_msg, send_msg_with_keyboard,
+    },
 };
 
 static INSTANT_QUEUE_TIMEOUT_MINUTES: i64 = 30;
 
 /// Called on timed out queues. Removes the chat queue and sends an
 /// informational Telegram message.
-async fn handle_queue_timeout(
+///
+/// Queues that timed out while the bot was offline (`late`) are announced or
+/// expired depending on configuration.
+pub async fn handle_queue_timeout(
     sc: &StateContainer,
+    settings: &Settings,
     bot: &Bot,
     chat_id: &ChatId,
     queue_id: &QueueId,
+    late: bool,
 ) -> Option<()> {
     let state = sc.read().await;
+    let queue = state.chats.get(chat_id)?.queues.get(queue_id)?;
+    let expire = late && settings.queue.missed_timeouts == MissedTimeouts::Expire;
 
-    // Remove chat queue and write new state.
-    let (state, removed_queue) = state.rm_chat_queue(chat_id, queue_id);
-    sc.write(state).await;
+    // Full queues are played once everyone has confirmed they're ready.
+    if queue.is_full() && !expire && settings.queue.ready_check_minutes > 0 {
+        let players_str = mk_players_str(queue, false, false);
+        let text = format!("{} queue: It's time to play!\n{}", queue_id, players_str);
+        close_status_msg(bot, chat_id, queue, &text).await;
 
-    let removed_queue = removed_queue?;
+        start_ready_check(sc, settings, bot, chat_id, queue_id).await;
+        return Some(());
+    }
+
+    // Remove chat queue and write new state. Full queues are played. The
+    // queue may have changed since it was read, e.g. if it was extended.
+    let removed_queue = sc
+        .update(|state| {
+            let queue = state.chats.get(chat_id)?.queues.get(queue_id)?;
+            if queue.timeout > Utc::now() {
+                return None;
+            }
+
+            let (state, removed_queue) = if queue.is_full() && !expire {
+                state.fire_chat_queue(chat_id, queue_id)
+            } else {
+                state.time_out_chat_queue(chat_id, queue_id)
+            };
+            Some((state, removed_queue?))
+        })
+        .await?;
+
+    if expire {
+        let players_str = mk_players_str(&removed_queue, false, false);
+        let text = format!(
+            "{} queue expired while the bot was offline.\n{}",
+            queue_id, players_str
+        );
+        close_status_msg(bot, chat_id, &removed_queue, &text).await;
+
+        return Some(());
+    }
+
+    let late_str = if late {
+        " (announced late, the bot was offline)"
+    } else {
+        ""
+    };
 
     // Inform players on Telegram about the timeout.
-    let text = if removed_queue.is_full() {
+    if removed_queue.is_full() {
+        let players_str = mk_players_str(&removed_queue, false, false);
+        let text = format!(
+            "{} queue: It's time to play!{}\n{}",
+            queue_id, late_str, players_str
+        );
+        close_status_msg(bot, chat_id, &removed_queue, &text).await;
+
+        // Edits don't notify anyone, so highlight players in a new message.
         let players_str = mk_players_str(&removed_queue, true, false);
-        format!("{} queue: It's time to play!\n{}", queue_id, players_str)
+        let text = format!(
+            "{} queue: It's time to play!{}\n{}",
+            queue_id, late_str, players_str
+        );
+        send_msg(bot, chat_id, &text, false).await;
+
+        start_queue_draft(bot, settings, sc, chat_id, queue_id, &removed_queue);
     } else {
         let players_str = mk_players_str(&removed_queue, false, false);
-        format!("{} queue timed out!\n{}", queue_id, players_str)
+        let text = format!("{} queue timed out!{}\n{}", queue_id, late_str, players_str);
+        close_status_msg(bot, chat_id, &removed_queue, &text).await;
+    }
+
+    Some(())
+}
+
+/// Returns reminders of a timed queue that haven't been sent, along with
+/// when they're due.
+fn unsent_reminders<'a>(
+    settings: &'a Settings,
+    queue_id: &QueueId,
+    queue: &'a Queue,
+) -> impl Iterator<Item = (i64, DateTime<Utc>)> + 'a {
+    let reminder_minutes = if queue_id.is_instant_queue() || queue.ready_check.is_some() {
+        &[][..]
+    } else {
+        &settings.queue.reminder_minutes[..]
     };
 
+    reminder_minutes
+        .iter()
+        .filter(|minutes| !queue.sent_reminders.contains(minutes))
+        .map(|minutes| (*minutes, queue.timeout - Duration::minutes(*minutes)))
+}
+
+/// Returns reminders of a timed queue that are due but haven't been sent.
+pub fn due_reminders(settings: &Settings, queue_id: &QueueId, queue: &Queue) -> Vec<i64> {
+    let now = Utc::now();
+
+    unsent_reminders(settings, queue_id, queue)
+        .filter(|(_, t)| *t <= now)
+        .map(|(minutes, _)| minutes)
+        .collect()
+}
+
+/// Returns when a queue next needs attention, i.e. when its next reminder is
+/// due, when it times out or when its ready check ends.
+pub fn next_queue_deadline(
+    settings: &Settings,
+    queue_id: &QueueId,
+    queue: &Queue,
+) -> DateTime<Utc> {
+    if let Some(ready_check) = &queue.ready_check {
+        return ready_check.deadline;
+    }
+
+    unsent_reminders(settings, queue_id, queue)
+        .map(|(_, t)| t)
+        .fold(queue.timeout, DateTime::min)
+}
+
+/// Called on queues with due reminders. Marks the reminders as sent and
+/// reminds players of the upcoming queue.
+pub async fn handle_queue_reminders(
+    sc: &StateContainer,
+    bot: &Bot,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    queue: &Queue,
+    reminders: Vec<i64>,
+) {
+    sc.update(|state| {
+        let state = state.set_queue_reminders_sent(chat_id, queue_id, &reminders);
+        Some((state, ()))
+    })
+    .await;
+
+    // Only send the closest reminder in case several are due at once, and
+    // none for queues created after the reminder would have been sent.
+    let Some(minutes) = reminders.into_iter().min() else {
+        return;
+    };
+
+    if queue.created_at > queue.timeout - Duration::minutes(minutes) {
+        return;
+    }
+
+    let players_str = mk_players_str(queue, true, false);
+    let missing = queue.size().saturating_sub(queue.num_players());
+    let needed = match missing {
+        0 => String::from("Queue is full!"),
+        1 => format!("1 more player needed, use {} to join!", queue.add_cmd),
+        n => format!("{} more players needed, use {} to join!", n, queue.add_cmd),
+    };
+
+    // Maybe players are most likely to fill the last spot.
+    let maybe_players = queue.get_maybe_players();
+    let needed = if missing == 1 && !maybe_players.is_empty() {
+        format!(
+            "{}\n{}, can you make it? Use {} yes to join!",
+            needed,
+            fmt_players(&maybe_players, true),
+            queue.add_cmd
+        )
+    } else {
+        needed
+    };
+
+    let text = format!(
+        "{} queue starts in {} minutes!\n{}\n{}",
+        queue_id, minutes, players_str, needed
+    );
     send_msg(bot, chat_id, &text, false).await;
+}
 
-    Some(())
+/// Starts a ready check on a full queue, and asks players in the lineup to
+/// confirm they're ready in a new status message.
+async fn start_ready_check(
+    sc: &StateContainer,
+    settings: &Settings,
+    bot: &Bot,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+) {
+    let deadline = Utc::now() + Duration::minutes(settings.queue.ready_check_minutes);
+
+    let started = sc
+        .update(|state| state.start_ready_check(chat_id, queue_id, deadline))
+        .await;
+    let Some(queue) = started else {
+        return;
+    };
+
+    let text = format!(
+        "{} queue is full, ready check!\n{}",
+        queue_id,
+        mk_ready_check_str(&queue, true)
+    );
+    post_queue_status(bot, sc, chat_id, queue_id, &queue, &text).await;
 }
 
-/// Task that polls and takes action for any queues that have timed out.
-pub async fn poll_for_timeouts(sc: StateContainer, tz: Tz, bot: Bot) {
-    loop {
-        let state = sc.read().await;
-        let t = fmt_naive_time(&Utc::now().with_timezone(&tz).time());
-
-        // Traverse all chat queues and look for timed out queues.
-        for (chat_id, chat) in &state.chats {
-            for (queue_id, queue) in &chat.queues {
-                // Note that we compare only HH:MM timestamps here and poll
-                // every second, so we shouldn't miss any timeouts.
-                if t == fmt_naive_time(&queue.timeout) {
-                    handle_queue_timeout(&sc, &bot, chat_id, queue_id).await;
-                }
-            }
-        }
+/// Removes a queue whose lineup has confirmed the ready check, and publishes
+/// the final lineup.
+async fn finish_ready_check(
+    sc: &StateContainer,
+    settings: &Settings,
+    bot: &Bot,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+) {
+    let removed_queue = sc
+        .update(|state| {
+            let (state, removed_queue) = state.fire_chat_queue(chat_id, queue_id);
+            Some((state, removed_queue?))
+        })
+        .await;
+
+    let Some(queue) = removed_queue else {
+        return;
+    };
+
+    let players_str = mk_players_str(&queue, false, false);
+    let text = format!("{} queue: Everyone's ready!\n{}", queue_id, players_str);
+    close_status_msg(bot, chat_id, &queue, &text).await;
+
+    let players_str = mk_players_str(&queue, true, false);
+    let text = format!("Match ready in {} queue! {}", queue_id, players_str);
+    send_msg(bot, chat_id, &text, false).await;
 
-        // Poll again after 1 second.
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await
+    start_queue_draft(bot, settings, sc, chat_id, queue_id, &queue);
+}
+
+/// Called on queues whose ready check deadline has passed. Drops players who
+/// didn't confirm, and asks promoted reserve players to confirm in their
+/// place.
+pub async fn handle_ready_check_timeout(
+    sc: &StateContainer,
+    settings: &Settings,
+    bot: &Bot,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+) {
+    let deadline = Utc::now() + Duration::minutes(settings.queue.ready_check_minutes);
+
+    let expired = sc
+        .update(|state| {
+            let (state, queue, dropped) = state.expire_ready_check(chat_id, queue_id, deadline)?;
+            Some((state, (queue, dropped)))
+        })
+        .await;
+    let Some((queue, dropped)) = expired else {
+        return;
+    };
+
+    if queue.is_ready() {
+        finish_ready_check(sc, settings, bot, chat_id, queue_id).await;
+        return;
+    }
+
+    let dropped_str = dropped
+        .iter()
+        .map(|player| escape(&player.to_string()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let dropped_str = format!("Dropped {}, who didn't confirm in time.", dropped_str);
+
+    if queue.ready_check.is_some() {
+        close_status_msg(bot, chat_id, &queue, &dropped_str).await;
+
+        let text = format!(
+            "{} queue ready check: {}\n{}",
+            queue_id,
+            dropped_str,
+            mk_ready_check_str(&queue, true)
+        );
+        post_queue_status(bot, sc, chat_id, queue_id, &queue, &text).await;
+    } else if queue.has_players() {
+        let text = format!(
+            "{} queue ready check failed. {}\n{}.\nUse {} to add/remove yourself from the queue!",
+            queue_id,
+            dropped_str,
+            mk_players_str(&queue, false, false),
+            queue.add_cmd
+        );
+        show_queue_status(bot, sc, chat_id, queue_id, &queue, &text, false).await;
+    } else {
+        let text = format!("{} queue ready check failed. {}", queue_id, dropped_str);
+        close_status_msg(bot, chat_id, &queue, &text).await;
+    }
+}
+
+/// Confirms that a player is ready in a queue, and publishes the lineup once
+/// everyone has confirmed.
+///
+/// Returns false if the player wasn't waiting to confirm a ready check.
+async fn confirm_ready(
+    sc: &StateContainer,
+    settings: &Settings,
+    bot: &Bot,
+    player: &Player,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+) -> bool {
+    let confirmed = sc
+        .update(|state| state.confirm_ready(chat_id, queue_id, player))
+        .await;
+    let Some(queue) = confirmed else {
+        return false;
+    };
+
+    if queue.is_ready() {
+        finish_ready_check(sc, settings, bot, chat_id, queue_id).await;
+    } else {
+        let text = format!(
+            "{} queue: {} is ready.\n{}",
+            queue_id,
+            escape(&player.to_string()),
+            mk_ready_check_str(&queue, false)
+        );
+        show_queue_status(bot, sc, chat_id, queue_id, &queue, &text, false).await;
+    }
+
+    true
+}
+
+/// Confirms that a player is ready in all of the chat's queues with an ongoing
+/// ready check.
+pub async fn ready(ctx: Context<'_>, player: Player, state: State) {
+    let queue_ids: Vec<QueueId> = state
+        .chats
+        .get(&ctx.chat_id)
+        .map(|chat| chat.queues.keys().cloned().collect())
+        .unwrap_or_default();
+
+    let mut confirmed = false;
+    for queue_id in queue_ids {
+        confirmed |= confirm_ready(
+            ctx.sc,
+            ctx.settings,
+            ctx.bot,
+            &player,
+            &ctx.chat_id,
+            &queue_id,
+        )
+        .await;
+    }
+
+    if !confirmed {
+        let text = "You have no ready check to confirm.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+    }
+}
+
+/// Handles Ready button presses on queue status messages.
+///
+/// Returns a short text to show to the player who pressed the button.
+pub async fn ready_button(ctx: Context<'_>, player: Player, queue_id: QueueId) -> String {
+    if confirm_ready(
+        ctx.sc,
+        ctx.settings,
+        ctx.bot,
+        &player,
+        &ctx.chat_id,
+        &queue_id,
+    )
+    .await
+    {
+        String::from("You're ready!")
+    } else {
+        String::from("You have no ready check to confirm in this queue.")
     }
 }
 
@@ -78,102 +422,501 @@ fn make_queue_strings(queues: Vec<(QueueId, Queue)>) -> Vec<String> {
         .collect()
 }
 
+/// Shows the status of an existing queue with Join/Maybe/Leave buttons.
+///
+/// Edits the queue's latest status message in place, unless `repost` is set
+/// or the edit fails, in which case a new status message is posted.
+pub async fn show_queue_status(
+    bot: &Bot,
+    sc: &StateContainer,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    queue: &Queue,
+    text: &str,
+    repost: bool,
+) {
+    let keyboard = mk_queue_keyboard(queue_id, queue);
+
+    if let Some(msg_id) = queue.status_msg_id {
+        if repost {
+            // Only the latest status message should have buttons.
+            edit_msg(bot, chat_id, msg_id, text, None).await;
+        } else if edit_msg(bot, chat_id, msg_id, text, Some(keyboard)).await {
+            return;
+        }
+    }
+
+    post_queue_status(bot, sc, chat_id, queue_id, queue, text).await;
+}
+
+/// Posts a new status message of an existing queue, and records it as the
+/// queue's latest status message.
+async fn post_queue_status(
+    bot: &Bot,
+    sc: &StateContainer,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    queue: &Queue,
+    text: &str,
+) {
+    let keyboard = mk_queue_keyboard(queue_id, queue);
+    let msg = send_msg_with_keyboard(bot, chat_id, text, keyboard).await;
+
+    if let Some(msg) = msg {
+        sc.update(|state| Some((state.set_queue_status_msg(chat_id, queue_id, msg.id), ())))
+            .await;
+    }
+}
+
+/// Shows the final status of a removed queue, without buttons.
+///
+/// Edits the queue's latest status message in place, or posts a new message
+/// if the edit fails.
+pub async fn close_status_msg(bot: &Bot, chat_id: &ChatId, queue: &Queue, text: &str) {
+    if let Some(msg_id) = queue.status_msg_id {
+        if edit_msg(bot, chat_id, msg_id, text, None).await {
+            return;
+        }
+    }
+
+    send_msg(bot, chat_id, text, false).await;
+}
+
+/// Mentions players who were promoted from the reserve to the lineup of a
+/// queue.
+async fn announce_promoted(
+    bot: &Bot,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    queue: &Queue,
+    promoted: &[Player],
+) {
+    if promoted.is_empty() {
+        return;
+    }
+
+    let confirm = if queue.ready_check.is_some() {
+        " Confirm with /ready!"
+    } else {
+        ""
+    };
+
+    let text = format!(
+        "{} queue: {} moved up from the reserve to the lineup!{}",
+        queue_id,
+        fmt_players(promoted, true),
+        confirm
+    );
+    send_msg(bot, chat_id, &text, false).await;
+}
+
+/// Shows the result of an add/remove operation, and mentions any players who
+/// were promoted to the lineup.
+async fn send_add_remove_result(
+    ctx: Context<'_>,
+    queue_id: &QueueId,
+    result: AddRemovePlayerResult,
+    op: AddRemovePlayerOp,
+    promoted: Vec<Player>,
+) {
+    // Construct message based on whether the queue is now full or not.
+    match result {
+        AddRemovePlayerResult::QueueFull(queue)
+            if queue_id.is_instant_queue() && queue.ready_check.is_none() =>
+        {
+            let text = mk_queue_status_msg(&queue, queue_id, &op);
+            close_status_msg(ctx.bot, &ctx.chat_id, &queue, &text).await;
+
+            if ctx.settings.queue.ready_check_minutes > 0 {
+                start_ready_check(ctx.sc, ctx.settings, ctx.bot, &ctx.chat_id, queue_id).await;
+                return;
+            }
+
+            // Remove instant queue once it's full.
+            ctx.sc
+                .update(|state| Some((state.fire_chat_queue(&ctx.chat_id, queue_id).0, ())))
+                .await;
+
+            let players_str = mk_players_str(&queue, true, false);
+            let text = format!("Match ready in {} queue! {}", queue_id, players_str);
+            send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+
+            start_queue_draft(
+                ctx.bot,
+                ctx.settings,
+                ctx.sc,
+                &ctx.chat_id,
+                queue_id,
+                &queue,
+            );
+        }
+        AddRemovePlayerResult::QueueEmpty(queue) => {
+            let text = mk_queue_status_msg(&queue, queue_id, &op);
+            close_status_msg(ctx.bot, &ctx.chat_id, &queue, &text).await;
+        }
+        AddRemovePlayerResult::PlayerQueued(queue) | AddRemovePlayerResult::QueueFull(queue) => {
+            announce_promoted(ctx.bot, &ctx.chat_id, queue_id, &queue, &promoted).await;
+
+            // Queue just became full if the added player filled the last spot.
+            let filled = queue.ready_check.is_none()
+                && matches!(op, AddRemovePlayerOp::PlayerAdded(_))
+                && queue.num_players() == queue.size();
+            let repost = filled && ctx.settings.queue.repost_when_full;
+
+            let text = mk_queue_status_msg(&queue, queue_id, &op);
+            show_queue_status(
+                ctx.bot,
+                ctx.sc,
+                &ctx.chat_id,
+                queue_id,
+                &queue,
+                &text,
+                repost,
+            )
+            .await;
+        }
+    }
+}
+
+/// Adds/removes `player` from a queue on behalf of `caller`, who sent the
+/// command.
 pub async fn add_remove(
-    username: Username,
+    ctx: Context<'_>,
+    player: Player,
+    caller: &Player,
     state: State,
-    chat_id: ChatId,
-    tz: &Tz,
-    time: Option<NaiveTime>,
-    sc: &StateContainer,
-) -> String {
+    time: Option<QueueTime>,
+    size: Option<usize>,
+    mode: JoinMode,
+) {
+    let now = Utc::now().with_timezone(ctx.tz);
+
     // Current time without seconds
-    let t_now = NaiveTime::from_hms_opt(
-        Utc::now().with_timezone(tz).time().hour(),
-        Utc::now().with_timezone(tz).time().minute(),
-        0,
-    )
-    .unwrap();
+    let t_now = NaiveTime::from_hms_opt(now.time().hour(), now.time().minute(), 0).unwrap();
 
     // Construct queue_id, timeout and add_cmd based on whether command
     // targeted a timed queue or not.
     let (queue_id, timeout, add_cmd) = match time {
-        Some(time) if time != t_now => {
-            let queue_id = QueueId::new(fmt_naive_time(&time));
-            let add_cmd = time.format("/%H%M").to_string();
-            (queue_id, time, add_cmd)
+        // Catch current minute commands and redirect to instant queue
+        Some(QueueTime { time, day }) if !(day == QueueDay::Next && time == t_now) => {
+            let Some(t) = day.resolve(time, &now) else {
+                let text = "Can't add to a queue in the past.";
+                send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+                return;
+            };
+
+            let queue_id = QueueId::timed(&t);
+            let add_cmd = mk_add_cmd(&t, &now);
+            (queue_id, t.with_timezone(&Utc), add_cmd)
         }
-        // Catch current or missing minute commands and redirect to instant queue
         _ => {
-            let queue_id = QueueId::new(String::from(""));
-            let timeout = Utc::now().with_timezone(tz).time()
-                + chrono::Duration::minutes(INSTANT_QUEUE_TIMEOUT_MINUTES);
+            let queue_id = QueueId::instant();
+            let timeout = Utc::now() + Duration::minutes(INSTANT_QUEUE_TIMEOUT_MINUTES);
             let add_cmd = String::from("/add");
             (queue_id, timeout, add_cmd)
         }
     };
 
-    // Add player and update state.
-    let (state, result, op) =
-        state.add_remove_player(&chat_id, &queue_id, add_cmd, timeout, username);
-    sc.write(state.clone()).await;
+    // Only admins or whoever added a player can change someone else's entry.
+    // Resizing a queue leaves the entry as is.
+    let resize = size.is_some() && mode == JoinMode::Toggle;
+    let queue = state
+        .chats
+        .get(&ctx.chat_id)
+        .and_then(|chat| chat.queues.get(&queue_id))
+        .filter(|_| !resize);
 
-    // Construct message based on whether the queue is now full or not.
-    match result {
-        AddRemovePlayerResult::QueueFull(queue) if queue_id.is_instant_queue() => {
-            let players_str = mk_players_str(&queue, true, false);
-            format!("Match ready in {} queue! {}", queue_id, players_str)
+    if let Some(queue) = queue.filter(|queue| queue.player_tier(&player).is_some()) {
+        if !can_remove(ctx.bot, ctx.settings, ctx.chat_id, queue, caller, &player).await {
+            let text = format!(
+                "Only chat admins or whoever added {} can remove them.",
+                escape(&player.to_string())
+            );
+            send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+            return;
         }
-        AddRemovePlayerResult::PlayerQueued(queue)
-        | AddRemovePlayerResult::QueueFull(queue)
-        | AddRemovePlayerResult::QueueEmpty(queue) => mk_queue_status_msg(&queue, &queue_id, &op),
+    }
+
+    // Add player and update state.
+    let updated = ctx
+        .sc
+        .update(|state| {
+            let (mut state, result, op, promoted) = state.add_remove_player(
+                &ctx.chat_id,
+                &queue_id,
+                AddRemovePlayerArgs {
+                    add_cmd,
+                    timeout,
+                    size,
+                    player: player.clone(),
+                    mode,
+                },
+            );
+
+            let removed = matches!(op, AddRemovePlayerOp::PlayerRemoved(_));
+            if let Some(user_id) = caller.id.filter(|_| !removed && player != *caller) {
+                state = state.set_player_added_by(&ctx.chat_id, &queue_id, &player, user_id);
+            }
+
+            Some((state, (result, op, promoted)))
+        })
+        .await;
+    let Some((result, op, promoted)) = updated else {
+        return;
+    };
+
+    let added = matches!(op, AddRemovePlayerOp::PlayerAdded(_));
+
+    send_add_remove_result(ctx, &queue_id, result, op, promoted).await;
+
+    if added {
+        notify_subscribers(ctx.bot, ctx.sc, &ctx.chat_id, ctx.tz, &queue_id).await;
     }
 }
 
-pub async fn remove_all(
-    username: Username,
+/// Handles Join/Maybe/Leave button presses on queue status messages. The
+/// player joins with given `mode`, or leaves if it's None.
+///
+/// Returns a short text to show to the player who pressed the button.
+pub async fn join_leave(
+    ctx: Context<'_>,
+    player: Player,
     state: State,
-    chat_id: ChatId,
-    sc: &StateContainer,
+    queue_id: QueueId,
+    mode: Option<JoinMode>,
 ) -> String {
+    let queue = state
+        .chats
+        .get(&ctx.chat_id)
+        .and_then(|chat| chat.queues.get(&queue_id));
+
+    let Some(queue) = queue else {
+        return String::from("This queue no longer exists.");
+    };
+
+    let mode = match (mode, queue.player_tier(&player)) {
+        (Some(JoinMode::Yes), Some(JoinMode::Yes)) => {
+            return String::from("You're already in this queue.")
+        }
+        (Some(JoinMode::Maybe), Some(JoinMode::Maybe)) => {
+            return String::from("You're already a maybe in this queue.")
+        }
+        (None, None) => return String::from("You're not in this queue."),
+        (Some(mode), _) => mode,
+        // Repeating the mode of the player's tier removes them.
+        (None, Some(JoinMode::Yes)) => JoinMode::Toggle,
+        (None, Some(tier)) => tier,
+    };
+
+    // Add/remove player and update state, unless the queue was removed in
+    // the meantime.
+    let updated = ctx
+        .sc
+        .update(|state| {
+            let queue = state.chats.get(&ctx.chat_id)?.queues.get(&queue_id)?;
+            let timeout = queue.timeout;
+            let add_cmd = queue.add_cmd.clone();
+
+            let (state, result, op, promoted) = state.add_remove_player(
+                &ctx.chat_id,
+                &queue_id,
+                AddRemovePlayerArgs {
+                    add_cmd,
+                    timeout,
+                    size: None,
+                    player,
+                    mode,
+                },
+            );
+            Some((state, (result, op, promoted)))
+        })
+        .await;
+    let Some((result, op, promoted)) = updated else {
+        return String::from("This queue no longer exists.");
+    };
+
+    let text = op.to_string();
+    let added = matches!(op, AddRemovePlayerOp::PlayerAdded(_));
+    send_add_remove_result(ctx, &queue_id, result, op, promoted).await;
+
+    if added {
+        notify_subscribers(ctx.bot, ctx.sc, &ctx.chat_id, ctx.tz, &queue_id).await;
+    }
+
+    text
+}
+
+pub async fn remove_all(ctx: Context<'_>, player: Player) {
     // Remove player and update state.
-    let (state, affected_queues) = state.rm_player(&chat_id, &username);
-    sc.write(state.clone()).await;
+    let affected_queues = ctx
+        .sc
+        .update(|state| Some(state.rm_player(&ctx.chat_id, &player)))
+        .await
+        .unwrap_or_default();
+
+    send_rm_player_results(ctx, &player, affected_queues).await;
+}
 
+/// Shows the results of removing a player from queues.
+pub async fn send_rm_player_results(
+    ctx: Context<'_>,
+    player: &Player,
+    affected_queues: HashMap<QueueId, (Queue, Vec<Player>)>,
+) {
     // Send queue status message for all affected queues.
-    affected_queues
-        .iter()
-        .map(|(queue_id, queue)| {
-            mk_queue_status_msg(
-                queue,
-                queue_id,
-                &AddRemovePlayerOp::PlayerRemoved(username.clone()),
-            )
+    for (queue_id, (queue, promoted)) in affected_queues {
+        let op = AddRemovePlayerOp::PlayerRemoved(player.clone());
+        let result = if queue.has_players() {
+            AddRemovePlayerResult::PlayerQueued(queue)
+        } else {
+            AddRemovePlayerResult::QueueEmpty(queue)
+        };
+
+        send_add_remove_result(ctx, &queue_id, result, op, promoted).await;
+    }
+}
+
+/// Moves a timed queue and its players to another time, and announces the
+/// change. An existing queue at the new time is only merged with if `merge`
+/// is set.
+pub async fn move_queue(ctx: Context<'_>, from: QueueTime, to: QueueTime, merge: bool) {
+    let now = Utc::now().with_timezone(ctx.tz);
+
+    let (Some(from_t), Some(to_t)) = (
+        from.day.resolve(from.time, &now),
+        to.day.resolve(to.time, &now),
+    ) else {
+        let text = "Can't move a queue to or from the past.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    };
+
+    let from_id = QueueId::timed(&from_t);
+    let to_id = QueueId::timed(&to_t);
+
+    if from_id == to_id {
+        let text = format!("{} queue is already at that time.", from_id);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    }
+
+    let add_cmd = mk_add_cmd(&to_t, &now);
+    let mut error = None;
+    let moved = ctx
+        .sc
+        .update(|state| {
+            let queues = state.chats.get(&ctx.chat_id).map(|chat| &chat.queues);
+
+            if !queues.is_some_and(|queues| queues.contains_key(&from_id)) {
+                error = Some(format!("No {} queue to move.", from_id));
+                return None;
+            }
+
+            if !merge && queues.is_some_and(|queues| queues.contains_key(&to_id)) {
+                error = Some(format!(
+                    "{} queue already exists. Add merge to the command to merge the queues.",
+                    to_id
+                ));
+                return None;
+            }
+
+            let (state, source, queue, merged) = state.move_queue(
+                &ctx.chat_id,
+                &from_id,
+                &to_id,
+                add_cmd,
+                to_t.with_timezone(&Utc),
+            )?;
+
+            // Don't send reminders that were due before the queue was moved.
+            let reminders = due_reminders(ctx.settings, &to_id, &queue);
+            let state = state.set_queue_reminders_sent(&ctx.chat_id, &to_id, &reminders);
+            Some((state, (source, queue, merged)))
         })
-        .collect::<Vec<String>>()
-        .join("\n")
+        .await;
+
+    let Some((source, queue, merged)) = moved else {
+        let text = error.unwrap_or_else(|| format!("No {} queue to move.", from_id));
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    let action = if merged { "merged into" } else { "moved to" };
+
+    let text = format!("{} queue {} {}.", from_id, action, to_id);
+    close_status_msg(ctx.bot, &ctx.chat_id, &source, &text).await;
+
+    // Status message buttons refer to the old queue, so always repost.
+    let text = format!(
+        "{} queue {} {}!\n{}.\nUse {} to add/remove yourself from the queue!",
+        from_id,
+        action,
+        to_id,
+        mk_players_str(&queue, true, false),
+        queue.add_cmd
+    );
+    show_queue_status(ctx.bot, ctx.sc, &ctx.chat_id, &to_id, &queue, &text, true).await;
+}
+
+/// Extends the instant queue's timeout by given minutes, defaulting to the
+/// instant queue's initial timeout.
+pub async fn extend(ctx: Context<'_>, minutes: Option<i64>) -> String {
+    let minutes = minutes.unwrap_or(INSTANT_QUEUE_TIMEOUT_MINUTES);
+    let duration = Duration::minutes(minutes);
+
+    let extended = ctx
+        .sc
+        .update(|state| state.extend_queue(&ctx.chat_id, &QueueId::instant(), duration))
+        .await;
+
+    match extended {
+        Some(queue) => {
+            format!(
+                "Instant queue extended by {} minutes, until {}.",
+                minutes,
+                queue.timeout.with_timezone(ctx.tz).format("%H:%M")
+            )
+        }
+        None => String::from("No instant queue to extend."),
+    }
 }
 
-pub fn list(state: State, chat_id: ChatId, tz: &Tz) -> String {
+/// Shows the chat's default queue size, or sets it if `size` is given.
+pub async fn queue_size(ctx: Context<'_>, size: Option<usize>) -> String {
+    match size {
+        Some(size) => {
+            ctx.sc
+                .update(|state| Some((state.set_chat_queue_size(&ctx.chat_id, Some(size)), ())))
+                .await;
+
+            format!("Default queue size set to {}.", size)
+        }
+        None => {
+            let size = ctx
+                .sc
+                .read()
+                .await
+                .chats
+                .get(&ctx.chat_id)
+                .map(|chat| chat.queue_size())
+                .unwrap_or(DEFAULT_QUEUE_SIZE);
+
+            format!(
+                "Default queue size is {}. Use /queuesize N to change it.",
+                size
+            )
+        }
+    }
+}
+
+pub fn list(state: State, chat_id: ChatId) -> String {
     let chat = state.chats.get(&chat_id);
     let queues = chat.map(|chat| chat.queues.clone());
 
     match queues {
         Some(queues) if !queues.is_empty() => {
-            let current_time = Utc::now().with_timezone(tz).time();
-
             let mut queues: Vec<(QueueId, Queue)> = queues.into_iter().collect();
-            queues.sort_by(|(_, a), (_, b)| {
-                let a_next_day = a.timeout < current_time;
-                let b_next_day = b.timeout < current_time;
-
-                if a_next_day == b_next_day {
-                    a.timeout.cmp(&b.timeout)
-                } else if a_next_day {
-                    Ordering::Greater
-                } else {
-                    Ordering::Less
-                }
-            });
+            queues.sort_by_key(|(_, queue)| queue.timeout);
 
             make_queue_strings(queues).join("\n")
         }
@@ -0,0 +1,231 @@
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use teloxide::{utils::html::escape, Bot};
+
+use crate::{
+    command::{QueueDay, RecurringCmd},
+    commands::{queue::show_queue_status, Context},
+    settings::Settings,
+    state::RecurringQueue,
+    state_container::StateContainer,
+    types::QueueId,
+    util::{mk_add_cmd, mk_ambiguous_name_msg, mk_players_str},
+};
+
+/// Returns the first occurrence of a recurring queue after `t`.
+fn next_occurrence(recurring: &RecurringQueue, t: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+    recurring
+        .weekdays
+        .iter()
+        .filter_map(|weekday| QueueDay::Weekday(*weekday).resolve(recurring.time, t))
+        .min()
+}
+
+/// Returns the next occurrence of a recurring queue that hasn't been opened
+/// yet.
+fn next_unopened_occurrence(recurring: &RecurringQueue, tz: &Tz) -> Option<DateTime<Tz>> {
+    let now = Utc::now();
+    let after = recurring.last_opened.map_or(now, |t| t.max(now));
+
+    next_occurrence(recurring, &after.with_timezone(tz))
+}
+
+fn fmt_recurring_queue(recurring: &RecurringQueue, tz: &Tz) -> String {
+    let weekdays = recurring
+        .weekdays
+        .iter()
+        .map(|weekday| weekday.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let players = if recurring.players.is_empty() {
+        String::new()
+    } else {
+        let players = recurring
+            .players
+            .iter()
+            .map(|player| player.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        format!(" ({})", escape(&players))
+    };
+
+    let size = recurring
+        .size
+        .map(|size| format!(" size={}", size))
+        .unwrap_or_default();
+
+    let status = if recurring.paused {
+        String::from("paused")
+    } else {
+        next_unopened_occurrence(recurring, tz)
+            .map(|t| format!("next {}", t.format("%a %d.%m. %H:%M")))
+            .unwrap_or_default()
+    };
+
+    format!(
+        "#{} {} {}{}{}, {}",
+        recurring.id,
+        weekdays,
+        recurring.time.format("%H:%M"),
+        size,
+        players,
+        status
+    )
+}
+
+/// Returns when the next occurrence of a recurring queue should be opened.
+pub fn next_opening(
+    recurring: &RecurringQueue,
+    settings: &Settings,
+    tz: &Tz,
+) -> Option<DateTime<Utc>> {
+    if recurring.paused {
+        return None;
+    }
+
+    let lead_time = Duration::hours(settings.queue.recurring_lead_time_hours);
+    let t = next_unopened_occurrence(recurring, tz)?;
+
+    Some(t.with_timezone(&Utc) - lead_time)
+}
+
+/// Opens queues for any recurring queue occurrences that are within the
+/// configured lead time, and informs the chat about them.
+pub async fn open_due_recurring_queues(
+    sc: &StateContainer,
+    settings: &Settings,
+    tz: &Tz,
+    bot: &Bot,
+) {
+    let state = sc.read().await;
+
+    for (chat_id, chat) in &state.chats {
+        for recurring in &chat.recurring_queues {
+            let due = next_opening(recurring, settings, tz).is_some_and(|t| t <= Utc::now());
+            let t = next_unopened_occurrence(recurring, tz);
+
+            let Some(t) = t.filter(|_| due) else {
+                continue;
+            };
+
+            let now = Utc::now().with_timezone(tz);
+            let queue_id = QueueId::timed(&t);
+            let add_cmd = mk_add_cmd(&t, &now);
+
+            let opened = sc
+                .update(|state| {
+                    state.open_recurring_queue(
+                        chat_id,
+                        recurring.id,
+                        &queue_id,
+                        add_cmd,
+                        t.with_timezone(&Utc),
+                    )
+                })
+                .await;
+
+            let Some(queue) = opened else {
+                continue;
+            };
+
+            let text = format!(
+                "{} queue opened (recurring #{}).\n{}.\nUse {} to add/remove yourself from the queue!",
+                queue_id,
+                recurring.id,
+                mk_players_str(&queue, false, false),
+                queue.add_cmd
+            );
+            show_queue_status(bot, sc, chat_id, &queue_id, &queue, &text, false).await;
+        }
+    }
+}
+
+/// Lists or manages the chat's recurring queues.
+pub async fn recurring(ctx: Context<'_>, cmd: RecurringCmd) -> String {
+    match cmd {
+        RecurringCmd::List => {
+            let recurring_queues = ctx
+                .sc
+                .read()
+                .await
+                .chats
+                .get(&ctx.chat_id)
+                .map(|chat| chat.recurring_queues.clone())
+                .unwrap_or_default();
+
+            if recurring_queues.is_empty() {
+                return String::from(
+                    "No recurring queues. Add one with e.g. /recurring add tue,thu 1930",
+                );
+            }
+
+            recurring_queues
+                .iter()
+                .map(|recurring| fmt_recurring_queue(recurring, ctx.tz))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        RecurringCmd::Add {
+            weekdays,
+            time,
+            players,
+            size,
+        } => {
+            let state = ctx.sc.read().await;
+            let mut resolved = Vec::new();
+            for name in players {
+                match state.player_by_name(name.clone()) {
+                    Some(player) => resolved.push(player),
+                    None => return mk_ambiguous_name_msg(&name),
+                }
+            }
+            let players = resolved;
+            ctx.sc
+                .update(|state| {
+                    let (state, recurring) =
+                        state.add_recurring_queue(&ctx.chat_id, weekdays, time, players, size);
+                    let text = format!(
+                        "Added recurring queue {}",
+                        fmt_recurring_queue(&recurring, ctx.tz)
+                    );
+                    Some((state, text))
+                })
+                .await
+                .unwrap_or_default()
+        }
+        RecurringCmd::Pause { id } | RecurringCmd::Resume { id } => {
+            let paused = matches!(cmd, RecurringCmd::Pause { .. });
+
+            let updated = ctx
+                .sc
+                .update(|state| {
+                    Some((
+                        state.set_recurring_queue_paused(&ctx.chat_id, id, paused)?,
+                        (),
+                    ))
+                })
+                .await;
+
+            match updated {
+                Some(()) => {
+                    let action = if paused { "paused" } else { "resumed" };
+                    format!("Recurring queue #{} {}.", id, action)
+                }
+                None => format!("No recurring queue #{}.", id),
+            }
+        }
+        RecurringCmd::Remove { id } => {
+            let removed = ctx
+                .sc
+                .update(|state| Some((state.rm_recurring_queue(&ctx.chat_id, id)?, ())))
+                .await;
+
+            match removed {
+                Some(()) => format!("Recurring queue #{} deleted.", id),
+                None => format!("No recurring queue #{}.", id),
+            }
+        }
+    }
+}
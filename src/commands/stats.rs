@@ -3,7 +3,7 @@ use chrono_tz::Tz;
 
 use crate::{services, settings::Settings, types::Username};
 
-fn index_to_pos(index: usize) -> String {
+pub fn index_to_pos(index: usize) -> String {
     match index {
         0 => "🥇".to_string(),
         1 => "🥈".to_string(),
@@ -217,16 +217,18 @@ fn stat_type_display_name(stat_type: &str) -> String {
     }
 }
 
+/// Leetify rating is stored as a small number (e.g. 0.05), display as +/- percentage
+pub fn format_leetify_rating(value: f32) -> String {
+    let pct = value * 100.0;
+    let sign = if pct >= 0.0 { "+" } else { "" };
+    format!("{sign}{pct:.2}")
+}
+
 fn format_stat_value(stat_type: &str, value: f32) -> String {
     match stat_type {
         // Opening and clutch are stored as decimals (0.xx), display as percentages
         "opening" | "clutch" => format!("{:.1}%", value * 100.0),
-        // Leetify rating is stored as a small number (e.g. 0.05), display as +/- percentage
-        "leetify" => {
-            let pct = value * 100.0;
-            let sign = if pct >= 0.0 { "+" } else { "" };
-            format!("{sign}{pct:.2}")
-        }
+        "leetify" => format_leetify_rating(value),
         // Aim, positioning, utility are direct ratings (e.g. 0.85)
         _ => format!("{:.2}", value),
     }
@@ -0,0 +1,151 @@
+use chrono::{NaiveTime, Utc};
+use chrono_tz::Tz;
+use teloxide::{types::ChatId, Bot};
+
+use crate::{
+    commands::Context,
+    state::Subscription,
+    state_container::StateContainer,
+    types::{Player, QueueId},
+    util::{fmt_players, send_msg},
+};
+
+fn fmt_subscription(subscription: &Subscription) -> String {
+    let window = subscription
+        .window
+        .map(|(from, to)| {
+            format!(
+                " starting between {} and {}",
+                from.format("%H:%M"),
+                to.format("%H:%M")
+            )
+        })
+        .unwrap_or_default();
+
+    let via = if subscription.dm {
+        "direct message"
+    } else {
+        "mention"
+    };
+
+    format!(
+        "Alerting you by {} when a queue{} reaches {} players.",
+        via, window, subscription.min_players
+    )
+}
+
+/// Shows the player's queue alert, or sets it if `min_players` is given.
+pub async fn subscribe(
+    ctx: Context<'_>,
+    player: Player,
+    min_players: Option<usize>,
+    window: Option<(NaiveTime, NaiveTime)>,
+    dm: bool,
+) -> String {
+    let Some(min_players) = min_players else {
+        let state = ctx.sc.read().await;
+        let subscription = state.chats.get(&ctx.chat_id).and_then(|chat| {
+            chat.subscriptions
+                .iter()
+                .find(|subscription| subscription.player == player)
+        });
+
+        return match subscription {
+            Some(subscription) => fmt_subscription(subscription),
+            None => String::from(
+                "No queue alert. Set one with e.g. /subscribe 3 to get pinged when a queue reaches 3 players.",
+            ),
+        };
+    };
+
+    if dm && player.id.is_none() {
+        return String::from("Can't send you direct messages.");
+    }
+
+    let subscription = Subscription {
+        player,
+        min_players,
+        window,
+        dm,
+    };
+    let text = fmt_subscription(&subscription);
+
+    ctx.sc
+        .update(|state| Some((state.subscribe(&ctx.chat_id, subscription), ())))
+        .await;
+
+    text
+}
+
+/// Removes the player's queue alert.
+pub async fn unsubscribe(ctx: Context<'_>, player: Player) -> String {
+    let removed = ctx
+        .sc
+        .update(|state| Some((state.unsubscribe(&ctx.chat_id, &player)?, ())))
+        .await;
+
+    match removed {
+        Some(()) => String::from("Queue alert removed."),
+        None => String::from("You have no queue alert."),
+    }
+}
+
+/// Alerts subscribers whose threshold a queue has reached, either by direct
+/// message or by mentioning them in the chat.
+pub async fn notify_subscribers(
+    bot: &Bot,
+    sc: &StateContainer,
+    chat_id: &ChatId,
+    tz: &Tz,
+    queue_id: &QueueId,
+) {
+    let alerted = sc
+        .update(|state| {
+            let queue = state.chats.get(chat_id)?.queues.get(queue_id)?.clone();
+
+            // Instant queues start right away.
+            let start = if queue_id.is_instant_queue() {
+                Utc::now()
+            } else {
+                queue.timeout
+            };
+            let start = start.with_timezone(tz).time();
+
+            let (state, subscriptions) = state.alert_subscribers(chat_id, queue_id, start)?;
+            Some((state, (queue, subscriptions)))
+        })
+        .await;
+
+    let Some((queue, subscriptions)) = alerted else {
+        return;
+    };
+
+    let status = format!(
+        "{} queue has {}/{} players",
+        queue_id,
+        queue.num_players(),
+        queue.size()
+    );
+
+    let mut mentions = vec![];
+
+    for subscription in subscriptions {
+        match subscription.player.id.filter(|_| subscription.dm) {
+            Some(user_id) => {
+                let text = format!("{}! Use {} in the group to join.", status, queue.add_cmd);
+                send_msg(bot, &ChatId::from(user_id), &text, false).await;
+            }
+            None => mentions.push(subscription.player),
+        }
+    }
+
+    if !mentions.is_empty() {
+        let text = format!(
+            "{}: {}! Use {} to join.",
+            fmt_players(&mentions, true),
+            status,
+            queue.add_cmd
+        );
+        send_msg(bot, chat_id, &text, false).await;
+    }
+}
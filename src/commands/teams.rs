@@ -0,0 +1,485 @@
+use chrono::Utc;
+use chrono_tz::Tz;
+use teloxide::{
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+    utils::html::escape,
+    Bot,
+};
+
+use crate::{
+    command::{CallbackCmd, QueueTime},
+    commands::{admin::resolve_queue_id, stats::format_leetify_rating, Context},
+    services::leetify::{team_ratings, TeamRating},
+    settings::Settings,
+    state::{Draft, Queue, State},
+    state_container::StateContainer,
+    types::{Player, QueueId},
+    util::{edit_msg, fmt_players, send_msg, send_msg_with_keyboard},
+};
+
+/// Number of players in each team.
+const TEAM_SIZE: usize = 5;
+
+/// How much less balanced than the best split a rerolled split may be, as a
+/// fraction of the difference between the highest and lowest rating.
+const REROLL_MAX_EXTRA_DIFF: f32 = 0.1;
+
+/// Returns splits of players into two teams of `TEAM_SIZE`, as indices into
+/// `ratings`, ordered from the most to the least balanced. Only splits nearly
+/// as balanced as the best one are included, for `/teams reroll` to cycle
+/// through. Mirrored splits are only included once.
+fn balanced_splits(ratings: &[f32]) -> Vec<(Vec<usize>, Vec<usize>)> {
+    let n = ratings.len();
+    let mut splits: Vec<(f32, Vec<usize>, Vec<usize>)> = vec![];
+
+    // The first player is always in the first team, to skip mirrored splits.
+    for mask in (1u32..1 << n).step_by(2) {
+        if mask.count_ones() as usize != TEAM_SIZE {
+            continue;
+        }
+
+        let (team1, team2): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| mask & 1 << i != 0);
+        let sum = |team: &[usize]| team.iter().map(|i| ratings[*i]).sum::<f32>();
+        let diff = (sum(&team1) - sum(&team2)).abs();
+
+        splits.push((diff, team1, team2));
+    }
+
+    splits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let max = ratings.iter().copied().fold(f32::MIN, f32::max);
+    let min = ratings.iter().copied().fold(f32::MAX, f32::min);
+    let max_diff =
+        splits.first().map_or(0.0, |(diff, _, _)| *diff) + (max - min) * REROLL_MAX_EXTRA_DIFF;
+
+    splits
+        .into_iter()
+        .take_while(|(diff, _, _)| *diff <= max_diff)
+        .map(|(_, team1, team2)| (team1, team2))
+        .collect()
+}
+
+fn fmt_rating(rating: TeamRating, value: f32) -> String {
+    match rating {
+        TeamRating::Premier => format!("{value:.0}"),
+        TeamRating::Leetify => format_leetify_rating(value),
+    }
+}
+
+/// Finds the queue to balance teams for: the queue at given time, or the
+/// earliest queue with enough players.
+fn find_queue(
+    state: &State,
+    chat_id: ChatId,
+    tz: &Tz,
+    time: Option<QueueTime>,
+) -> Option<(QueueId, Queue)> {
+    let chat = state.chats.get(&chat_id)?;
+
+    if time.is_some() {
+        let queue_id = resolve_queue_id(time, tz)?;
+        let queue = chat.queues.get(&queue_id)?.clone();
+
+        return Some((queue_id, queue));
+    }
+
+    let mut queues: Vec<(QueueId, Queue)> = chat.queues.clone().into_iter().collect();
+    queues.sort_by_key(|(_, queue)| queue.timeout);
+
+    queues
+        .into_iter()
+        .find(|(_, queue)| queue.num_players() >= TEAM_SIZE * 2)
+}
+
+/// Returns the first ten players of a queue, lineup first, along with
+/// players who sit out. Returns None if the queue doesn't have enough players.
+fn team_players(queue: &Queue) -> Option<(Vec<Player>, Vec<Player>)> {
+    let (mut players, reserve) = queue.get_players();
+    players.extend(reserve.unwrap_or_default());
+
+    if players.len() < TEAM_SIZE * 2 {
+        return None;
+    }
+
+    let sitting_out = players.split_off(TEAM_SIZE * 2);
+
+    Some((players, sitting_out))
+}
+
+/// Splits the first ten players of a queue into two teams with minimal
+/// rating difference. Rerolling picks the next best split.
+pub async fn teams(
+    ctx: Context<'_>,
+    state: State,
+    time: Option<QueueTime>,
+    rating: TeamRating,
+    reroll: bool,
+) -> String {
+    let Some((queue_id, queue)) = find_queue(&state, ctx.chat_id, ctx.tz, time) else {
+        return format!(
+            "No queue with {} players to split into teams.",
+            TEAM_SIZE * 2
+        );
+    };
+
+    let Some((players, sitting_out)) = team_players(&queue) else {
+        return format!(
+            "Teams need {} players, {} queue has {}.",
+            TEAM_SIZE * 2,
+            queue_id,
+            queue.num_players()
+        );
+    };
+
+    let usernames: Vec<_> = players
+        .iter()
+        .map(|player| ctx.settings.players.mapping_username(player))
+        .collect();
+    let ratings = team_ratings(ctx.settings, &usernames, rating).await;
+
+    // Players without a rating count as an average player.
+    let known: Vec<f32> = ratings.iter().flatten().copied().collect();
+    let fallback = if known.is_empty() {
+        0.0
+    } else {
+        known.iter().sum::<f32>() / known.len() as f32
+    };
+    let values: Vec<f32> = ratings.iter().map(|r| r.unwrap_or(fallback)).collect();
+
+    let rerolls = ctx
+        .sc
+        .update(|state| state.reroll_teams(&ctx.chat_id, &queue_id, reroll))
+        .await;
+
+    let Some(rerolls) = rerolls else {
+        return format!("{} queue no longer exists.", queue_id);
+    };
+
+    let splits = balanced_splits(&values);
+    let (team1, team2) = &splits[rerolls % splits.len()];
+
+    let fmt_team = |name: &str, team: &[usize]| {
+        let avg = team.iter().map(|i| values[*i]).sum::<f32>() / team.len() as f32;
+        let players = team
+            .iter()
+            .map(|i| {
+                let rating = ratings[*i]
+                    .map(|value| fmt_rating(rating, value))
+                    .unwrap_or_else(|| String::from("no rating"));
+
+                format!("{} ({})", escape(&players[*i].to_string()), rating)
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        format!("{} (avg {}): {}", name, fmt_rating(rating, avg), players)
+    };
+
+    let rating_name = match rating {
+        TeamRating::Premier => "premier",
+        TeamRating::Leetify => "Leetify",
+    };
+
+    let sitting_out = if sitting_out.is_empty() {
+        String::new()
+    } else {
+        let names = sitting_out
+            .iter()
+            .map(Player::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        format!("\nSitting out: {}", escape(&names))
+    };
+
+    let reroll = if splits.len() > 1 {
+        "Use /teams reroll for another split."
+    } else {
+        "No other split is as balanced."
+    };
+
+    format!(
+        "Teams for {} queue by {} rating:\n{}\n{}{}\n{}",
+        queue_id,
+        rating_name,
+        fmt_team("Team 1", team1),
+        fmt_team("Team 2", team2),
+        sitting_out,
+        reroll
+    )
+}
+
+fn fmt_draft_player(draft: &Draft, index: usize) -> String {
+    let (player, rating) = &draft.players[index];
+    let rating = rating
+        .map(|value| fmt_rating(TeamRating::Leetify, value))
+        .unwrap_or_else(|| String::from("no rating"));
+
+    format!("{} ({})", player, rating)
+}
+
+/// Describes the teams of a captain draft, and whose turn it is to pick. When
+/// `highlight` is set, the captain whose turn it is is mentioned.
+fn fmt_draft(queue_id: &QueueId, draft: &Draft, highlight: bool) -> String {
+    let teams = draft
+        .teams
+        .iter()
+        .enumerate()
+        .map(|(team, picked)| {
+            let players = picked
+                .iter()
+                .map(|i| escape(&fmt_draft_player(draft, *i)))
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            format!(
+                "Team {}: {}",
+                escape(&draft.captain(team).to_string()),
+                players
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    if draft.is_finished() {
+        return format!("{} queue teams are set!\n{}", queue_id, teams);
+    }
+
+    let captain = draft.captain(draft.picking_team()).clone();
+
+    format!(
+        "Captain draft for {} queue!\n{}\n{} picks next.",
+        queue_id,
+        teams,
+        fmt_players(&[captain], highlight)
+    )
+}
+
+/// Constructs buttons for picking each remaining player of a captain draft.
+fn mk_draft_keyboard(queue_id: &QueueId, draft: &Draft) -> InlineKeyboardMarkup {
+    let buttons: Vec<InlineKeyboardButton> = draft
+        .remaining()
+        .into_iter()
+        .map(|index| {
+            let pick = CallbackCmd::Pick {
+                queue_id: queue_id.clone(),
+                index,
+            };
+
+            InlineKeyboardButton::callback(fmt_draft_player(draft, index), pick.to_data())
+        })
+        .collect();
+
+    InlineKeyboardMarkup::new(buttons.chunks(2).map(|row| row.to_vec()))
+}
+
+/// Starts a captain draft with given players and posts it. Without given
+/// captains, the two players with the highest Leetify rating are captains.
+async fn start_draft(
+    bot: &Bot,
+    settings: &Settings,
+    sc: &StateContainer,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    players: Vec<Player>,
+    captains: Vec<Player>,
+) {
+    let usernames: Vec<_> = players
+        .iter()
+        .map(|player| settings.players.mapping_username(player))
+        .collect();
+    let ratings = team_ratings(settings, &usernames, TeamRating::Leetify).await;
+
+    let captains: Vec<usize> = if captains.is_empty() {
+        // Players without a rating are picked last, ties in queue order.
+        let mut by_rating: Vec<usize> = (0..players.len()).collect();
+        by_rating.sort_by(|a, b| {
+            let rating = |i: &usize| ratings[*i].unwrap_or(f32::NEG_INFINITY);
+            rating(b).total_cmp(&rating(a))
+        });
+        by_rating.truncate(2);
+        by_rating
+    } else {
+        captains
+            .iter()
+            .filter_map(|captain| players.iter().position(|player| player == captain))
+            .collect()
+    };
+
+    let captains = match captains[..] {
+        [captain1, captain2] if captain1 != captain2 => [captain1, captain2],
+        _ => {
+            let text = format!("Captains must be two players of the {} queue.", queue_id);
+            send_msg(bot, chat_id, &text, false).await;
+            return;
+        }
+    };
+
+    let draft = Draft::new(players.into_iter().zip(ratings).collect(), captains);
+
+    sc.update(|state| Some((state.start_draft(chat_id, queue_id, draft.clone()), ())))
+        .await;
+
+    let text = fmt_draft(queue_id, &draft, true);
+    let keyboard = mk_draft_keyboard(queue_id, &draft);
+    let msg = send_msg_with_keyboard(bot, chat_id, &text, keyboard).await;
+
+    if let Some(msg) = msg {
+        sc.update(|state| Some((state.set_draft_msg(chat_id, queue_id, msg.id), ())))
+            .await;
+    }
+}
+
+/// Starts a captain draft for a queue that is ready to play, if it has room
+/// for two full teams and drafts are enabled. The draft is started in the
+/// background, as fetching ratings can take a while.
+pub fn start_queue_draft(
+    bot: &Bot,
+    settings: &Settings,
+    sc: &StateContainer,
+    chat_id: &ChatId,
+    queue_id: &QueueId,
+    queue: &Queue,
+) {
+    if !settings.queue.captain_draft || queue.size() < TEAM_SIZE * 2 {
+        return;
+    }
+
+    let Some((players, _)) = team_players(queue) else {
+        return;
+    };
+
+    let bot = bot.clone();
+    let settings = settings.clone();
+    let sc = sc.clone();
+    let chat_id = *chat_id;
+    let queue_id = queue_id.clone();
+
+    tokio::spawn(async move {
+        start_draft(&bot, &settings, &sc, &chat_id, &queue_id, players, vec![]).await;
+    });
+}
+
+/// Starts a captain draft for the queue at given time, or the earliest queue
+/// with enough players. Players sitting out aren't part of the draft.
+pub async fn draft(ctx: Context<'_>, state: State, time: Option<QueueTime>, captains: Vec<Player>) {
+    let Some((queue_id, queue)) = find_queue(&state, ctx.chat_id, ctx.tz, time) else {
+        let text = format!("No queue with {} players to draft.", TEAM_SIZE * 2);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    let Some((players, _)) = team_players(&queue) else {
+        let text = format!(
+            "A draft needs {} players, {} queue has {}.",
+            TEAM_SIZE * 2,
+            queue_id,
+            queue.num_players()
+        );
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    start_draft(
+        ctx.bot,
+        ctx.settings,
+        ctx.sc,
+        &ctx.chat_id,
+        &queue_id,
+        players,
+        captains,
+    )
+    .await;
+}
+
+/// Handles player buttons of a captain draft. Publishes the final rosters once
+/// everyone has been picked.
+///
+/// Returns a short text to show to the player who pressed the button.
+pub async fn pick(ctx: Context<'_>, player: Player, queue_id: QueueId, index: usize) -> String {
+    let mut error = None;
+    let picked = ctx
+        .sc
+        .update(|state| {
+            let draft = state
+                .chats
+                .get(&ctx.chat_id)
+                .and_then(|chat| chat.drafts.get(&queue_id))
+                .filter(|draft| !draft.is_stale(Utc::now()));
+
+            let Some(draft) = draft else {
+                error = Some(String::from("This draft is over."));
+                return None;
+            };
+
+            let captain = draft.captain(draft.picking_team());
+            if *captain != player {
+                error = Some(format!("It's {}'s turn to pick.", captain));
+                return None;
+            }
+
+            state.pick_draft_player(&ctx.chat_id, &queue_id, &player, index)
+        })
+        .await;
+
+    let Some(draft) = picked else {
+        return error.unwrap_or_else(|| String::from("That player has already been picked."));
+    };
+
+    if draft.is_finished() {
+        let text = fmt_draft(&queue_id, &draft, false);
+
+        if let Some(msg_id) = draft.msg_id {
+            edit_msg(ctx.bot, &ctx.chat_id, msg_id, &text, None).await;
+        }
+
+        // Edits don't notify anyone, so publish the rosters in a new message.
+        let teams = draft
+            .teams
+            .iter()
+            .map(|team| {
+                let players: Vec<Player> =
+                    team.iter().map(|i| draft.players[*i].0.clone()).collect();
+
+                fmt_players(&players, true)
+            })
+            .collect::<Vec<String>>()
+            .join("\nvs.\n");
+        let text = format!("Teams for {} queue:\n{}", queue_id, teams);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+    } else if let Some(msg_id) = draft.msg_id {
+        let text = fmt_draft(&queue_id, &draft, false);
+        let keyboard = mk_draft_keyboard(&queue_id, &draft);
+        edit_msg(ctx.bot, &ctx.chat_id, msg_id, &text, Some(keyboard)).await;
+    }
+
+    format!("Picked {}.", draft.players[index].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_teams_by_minimal_rating_difference() {
+        let ratings = [10.0, 9.0, 8.0, 7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 2.0];
+        let splits = balanced_splits(&ratings);
+
+        let sum = |team: &[usize]| team.iter().map(|i| ratings[*i]).sum::<f32>();
+        let (team1, _) = &splits[0];
+        assert!(team1.contains(&0));
+
+        // Rerolls only cycle through splits nearly as balanced as the best,
+        // which here are the perfectly balanced ones. Mirrored splits are
+        // skipped.
+        assert!(splits.len() > 1);
+        for (team1, team2) in &splits {
+            assert_eq!(sum(team1), sum(team2));
+            assert!(team1.contains(&0));
+        }
+
+        // Only one split is within a tenth of the rating spread of the best.
+        let ratings = [9.0, 9.0, 9.0, 6.0, 5.0, 5.0, 5.0, 5.0, 0.0, 0.0];
+        assert_eq!(balanced_splits(&ratings).len(), 1);
+    }
+}
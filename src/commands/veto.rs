@@ -0,0 +1,185 @@
+use teloxide::{
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    utils::html::escape,
+};
+
+use crate::{
+    command::CallbackCmd,
+    commands::Context,
+    state::{Veto, VetoStep},
+    types::{Player, QueueId},
+    util::{edit_msg, fmt_players, send_msg, send_msg_with_keyboard},
+};
+
+/// Formats a map name for display, e.g. `Mirage` for `de_mirage`.
+fn fmt_map(map: &str) -> String {
+    let name = map.split_once('_').map_or(map, |(_, name)| name);
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the name of a veto side: the given player, or "Side 1/2".
+fn side_name(veto: &Veto, side: usize, highlight: bool) -> String {
+    match &veto.sides {
+        Some(sides) => fmt_players(std::slice::from_ref(&sides[side]), highlight),
+        None => format!("Side {}", side + 1),
+    }
+}
+
+/// Returns the maps to be played in a finished veto, formatted for display.
+fn fmt_played_maps(veto: &Veto) -> String {
+    veto.played_maps()
+        .into_iter()
+        .map(|map| escape(&fmt_map(map)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Describes the maps banned and picked in a veto so far, and whose turn it
+/// is. When `highlight` is set, the player whose turn it is is mentioned.
+fn fmt_veto(queue_id: &QueueId, veto: &Veto, highlight: bool) -> String {
+    let mut lines = vec![format!(
+        "Map veto for {} queue (BO{}):",
+        queue_id, veto.best_of
+    )];
+
+    for (step, (map, action)) in veto.actions.iter().zip(&veto.steps).enumerate() {
+        let action = match action {
+            VetoStep::Ban => "banned",
+            VetoStep::Pick => "picked",
+        };
+
+        lines.push(format!(
+            "{} {} {}",
+            side_name(veto, step % 2, false),
+            action,
+            escape(&fmt_map(&veto.maps[*map]))
+        ));
+    }
+
+    match veto.next_step() {
+        Some((side, step)) => {
+            let step = match step {
+                VetoStep::Ban => "bans",
+                VetoStep::Pick => "picks",
+            };
+
+            lines.push(format!(
+                "{} {} next.",
+                side_name(veto, side, highlight),
+                step
+            ));
+        }
+        None => lines.push(format!("Maps: {}", fmt_played_maps(veto))),
+    }
+
+    lines.join("\n")
+}
+
+/// Constructs buttons for each remaining map of a veto.
+fn mk_veto_keyboard(queue_id: &QueueId, veto: &Veto) -> InlineKeyboardMarkup {
+    let buttons: Vec<InlineKeyboardButton> = veto
+        .remaining()
+        .into_iter()
+        .map(|map| {
+            let cmd = CallbackCmd::Veto {
+                queue_id: queue_id.clone(),
+                map,
+            };
+
+            InlineKeyboardButton::callback(fmt_map(&veto.maps[map]), cmd.to_data())
+        })
+        .collect();
+
+    InlineKeyboardMarkup::new(buttons.chunks(3).map(|row| row.to_vec()))
+}
+
+/// Starts a map veto for the most recently played queue. Without given sides,
+/// anyone can ban and pick maps.
+pub async fn veto(ctx: Context<'_>, best_of: usize, sides: Vec<Player>) {
+    let sides = match sides.as_slice() {
+        [] => None,
+        [side1, side2] if side1 != side2 => Some([side1.clone(), side2.clone()]),
+        _ => {
+            send_msg(
+                ctx.bot,
+                &ctx.chat_id,
+                "Sides must be two different players.",
+                false,
+            )
+            .await;
+            return;
+        }
+    };
+
+    let Some(veto) = Veto::new(ctx.settings.queue.map_pool.clone(), best_of, sides) else {
+        let text = format!("The map pool is too small for a BO{} veto.", best_of);
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+        return;
+    };
+
+    let started = ctx
+        .sc
+        .update(|state| state.start_veto(&ctx.chat_id, veto.clone()))
+        .await;
+
+    let Some(queue_id) = started else {
+        let text = "No queue has been ready to play yet.";
+        send_msg(ctx.bot, &ctx.chat_id, text, false).await;
+        return;
+    };
+
+    let text = fmt_veto(&queue_id, &veto, true);
+    let keyboard = mk_veto_keyboard(&queue_id, &veto);
+    let msg = send_msg_with_keyboard(ctx.bot, &ctx.chat_id, &text, keyboard).await;
+
+    if let Some(msg) = msg {
+        ctx.sc
+            .update(|state| Some((state.set_veto_msg(&ctx.chat_id, &queue_id, msg.id), ())))
+            .await;
+    }
+}
+
+/// Handles map buttons of a veto.
+///
+/// Returns a short text to show to the player who pressed the button.
+pub async fn veto_button(
+    ctx: Context<'_>,
+    player: Player,
+    queue_id: QueueId,
+    map: usize,
+) -> String {
+    let vetoed = ctx
+        .sc
+        .update(|state| state.veto_map(&ctx.chat_id, &queue_id, &player, map))
+        .await;
+
+    let Some(veto) = vetoed else {
+        return String::from("You can't ban or pick that map right now.");
+    };
+
+    let text = fmt_veto(&queue_id, &veto, false);
+
+    if veto.is_finished() {
+        if let Some(msg_id) = veto.msg_id {
+            edit_msg(ctx.bot, &ctx.chat_id, msg_id, &text, None).await;
+        }
+
+        let text = format!("Maps for {} queue: {}", queue_id, fmt_played_maps(&veto));
+        send_msg(ctx.bot, &ctx.chat_id, &text, false).await;
+    } else if let Some(msg_id) = veto.msg_id {
+        let keyboard = mk_veto_keyboard(&queue_id, &veto);
+        edit_msg(ctx.bot, &ctx.chat_id, msg_id, &text, Some(keyboard)).await;
+    }
+
+    let action = match veto.steps[veto.actions.len() - 1] {
+        VetoStep::Ban => "Banned",
+        VetoStep::Pick => "Picked",
+    };
+
+    format!("{} {}.", action, fmt_map(&veto.maps[map]))
+}
@@ -1,16 +1,29 @@
-use crate::state_container::StateContainer;
+use crate::{
+    settings::{Settings, StorageBackend},
+    state_container::StateContainer,
+    storage::{json::JsonStorage, Storage},
+};
 use chrono_tz::Tz;
 use clap::Parser;
-use color_eyre::Result;
-use teloxide::{types::Message, utils::client_from_env, Bot};
+use color_eyre::{eyre::eyre, Result};
+use teloxide::{
+    dispatching::UpdateFilterExt,
+    dptree,
+    prelude::Dispatcher,
+    types::{CallbackQuery, Message, Update},
+    utils::client_from_env,
+    Bot,
+};
 
 mod bot;
 mod command;
 mod commands;
+mod scheduler;
 mod services;
 mod settings;
 mod state;
 mod state_container;
+mod storage;
 mod types;
 mod util;
 
@@ -19,6 +32,19 @@ mod util;
 struct Args {
     #[arg(short, long, default_value = "UTC")]
     tz: String,
+
+    /// Storage backend, overrides the one in settings.
+    #[arg(long, value_enum)]
+    storage_backend: Option<StorageBackend>,
+
+    /// Path of the state file or database, overrides the one in settings.
+    #[arg(long)]
+    storage_path: Option<String>,
+
+    /// Imports state from given JSON state file into configured storage and
+    /// exits.
+    #[arg(long)]
+    import: Option<String>,
 }
 
 #[tokio::main]
@@ -26,44 +52,89 @@ async fn main() -> Result<()> {
     color_eyre::install()?;
     let settings = settings::read_settings()?;
 
-    // Try restoring state from file, or default to empty state.
-    let sc = StateContainer::try_read_from_file().await?;
-
     let args = Args::parse();
     let tz: Tz = args.tz.parse().unwrap();
 
+    let backend = args.storage_backend.unwrap_or(settings.storage.backend);
+    let path = args
+        .storage_path
+        .or(settings.storage.path.clone())
+        .unwrap_or_else(|| backend.default_path().to_string());
+    let storage = storage::open(backend, &path)?;
+
+    if let Some(import_path) = args.import {
+        if !std::path::Path::new(&import_path).exists() {
+            return Err(eyre!("State file {} does not exist", import_path));
+        }
+
+        let state = JsonStorage::new(&import_path).load(&tz)?;
+        storage.save(&state)?;
+        println!(
+            "Imported {} chats from {} to {}",
+            state.chats.len(),
+            import_path,
+            path
+        );
+        return Ok(());
+    }
+
+    // Try restoring state from storage, or default to empty state.
+    let sc = StateContainer::load(storage, &tz).await?;
+
     // Initialize the Telegram bot API.
     pretty_env_logger::init();
     let bot = Bot::with_client(&settings.teloxide.bot_api_token, client_from_env());
 
-    // Spawn a new task that polls for queues that have timed out.
-    tokio::spawn(commands::queue::poll_for_timeouts(
+    // Spawn a new task that takes action on queue timeouts, reminders and
+    // recurring queues.
+    tokio::spawn(scheduler::run(
         sc.clone(),
+        settings.clone(),
         tz,
         bot.clone(),
     ));
 
-    // Start polling for Telegram messages.
-    teloxide::repl(bot.clone(), move |message: Message, bot: Bot| {
-        let settings = settings.clone();
-        let sc = sc.clone();
+    // Start polling for Telegram messages and inline keyboard button presses.
+    let handler =
+        dptree::entry()
+            .branch(Update::filter_message().endpoint(
+                move |message: Message, bot: Bot, settings: Settings, sc: StateContainer| {
+                    async move {
+                        let msg_text = message.text();
 
-        async move {
-            let msg_text = message.text();
+                        // Only attempt parsing message if there's any message text.
+                        if let Some(msg_text) = msg_text {
+                            let cmd = command::parse_cmd(msg_text);
 
-            // Only attempt parsing message if there's any message text.
-            if let Some(msg_text) = msg_text {
-                let cmd = command::parse_cmd(msg_text);
+                            if let Ok(Some(cmd)) = cmd {
+                                bot::handle_cmd(settings, sc, tz, bot, message, cmd).await;
+                            }
+                        }
 
-                if let Ok(Some(cmd)) = cmd {
-                    bot::handle_cmd(settings, sc, tz, bot, message, cmd).await;
-                }
-            }
+                        teloxide::respond(())
+                    }
+                },
+            ))
+            .branch(
+                Update::filter_callback_query().endpoint(
+                    move |query: CallbackQuery,
+                          bot: Bot,
+                          settings: Settings,
+                          sc: StateContainer| async move {
+                        bot::handle_callback_query(settings, sc, tz, bot, query).await;
 
-            Ok(())
-        }
-    })
-    .await;
+                        teloxide::respond(())
+                    },
+                ),
+            );
+
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![settings, sc])
+        .default_handler(|_upd| Box::pin(async {}))
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
 
     Ok(())
 }
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::Bot;
+
+use crate::{
+    commands::{
+        attendance::{attendance_check_due, check_due_attendance},
+        queue::{
+            due_reminders, handle_queue_reminders, handle_queue_timeout,
+            handle_ready_check_timeout, next_queue_deadline,
+        },
+        recurring::{next_opening, open_due_recurring_queues},
+    },
+    settings::Settings,
+    state::State,
+    state_container::StateContainer,
+};
+
+/// Upper bound for how long the scheduler sleeps at a time, so that wall
+/// clock changes (e.g. host suspend) can't delay deadlines for long.
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Returns the earliest point in time when the scheduler needs to act.
+fn next_deadline(state: &State, settings: &Settings, tz: &Tz) -> Option<DateTime<Utc>> {
+    let queue_deadlines = state.chats.values().flat_map(|chat| {
+        chat.queues
+            .iter()
+            .map(|(queue_id, queue)| next_queue_deadline(settings, queue_id, queue))
+    });
+
+    let recurring_deadlines = state.chats.values().flat_map(|chat| {
+        chat.recurring_queues
+            .iter()
+            .filter_map(|recurring| next_opening(recurring, settings, tz))
+    });
+
+    let attendance_deadlines = state.chats.values().flat_map(|chat| {
+        chat.history
+            .iter()
+            .filter_map(|record| attendance_check_due(settings, record))
+    });
+
+    queue_deadlines
+        .chain(recurring_deadlines)
+        .chain(attendance_deadlines)
+        .min()
+}
+
+/// Takes action on everything that is due: timed out queues, reminders, ready
+/// checks, recurring queues that should be opened and played queues whose
+/// attendance should be checked.
+///
+/// On `startup`, queues that timed out while the bot was offline are handled
+/// as late.
+async fn handle_due(sc: &StateContainer, settings: &Settings, tz: &Tz, bot: &Bot, startup: bool) {
+    let state = sc.read().await;
+    let now = Utc::now();
+
+    for (chat_id, chat) in &state.chats {
+        for (queue_id, queue) in &chat.queues {
+            if let Some(ready_check) = &queue.ready_check {
+                if ready_check.deadline <= now {
+                    handle_ready_check_timeout(sc, settings, bot, chat_id, queue_id).await;
+                }
+                continue;
+            }
+
+            if queue.timeout <= now {
+                handle_queue_timeout(sc, settings, bot, chat_id, queue_id, startup).await;
+                continue;
+            }
+
+            let reminders = due_reminders(settings, queue_id, queue);
+            if !reminders.is_empty() {
+                handle_queue_reminders(sc, bot, chat_id, queue_id, queue, reminders).await;
+            }
+        }
+    }
+
+    open_due_recurring_queues(sc, settings, tz, bot).await;
+
+    check_due_attendance(sc, settings, bot).await;
+}
+
+/// Task that sleeps until the next deadline and takes action on it. The task
+/// is woken early whenever state changes, as deadlines may have changed too.
+pub async fn run(sc: StateContainer, settings: Settings, tz: Tz, bot: Bot) {
+    let mut startup = true;
+
+    loop {
+        handle_due(&sc, &settings, &tz, &bot, startup).await;
+        startup = false;
+
+        let state = sc.read().await;
+        let sleep = next_deadline(&state, &settings, &tz)
+            .map(|t| (t - Utc::now()).to_std().unwrap_or_default())
+            .unwrap_or(MAX_SLEEP)
+            .min(MAX_SLEEP);
+
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = sc.changed() => {}
+        }
+    }
+}
@@ -201,6 +201,29 @@ pub struct LeetifyMiniProfile {
     pub recent_matches: Vec<RecentMatch>,
 }
 
+/// Premier ratings below this are old CSGO premier ranks.
+const MIN_PREMIER_RATING: u32 = 1000;
+
+impl LeetifyMiniProfile {
+    /// Returns the player's matchmaking skill level for given rank type, or
+    /// `None` if the player is unranked or only has an old CSGO premier rank.
+    pub fn skill_level(&self, rank_type: &str) -> Option<u32> {
+        self.ranks
+            .iter()
+            .find(|r| {
+                if rank_type == "wingman" {
+                    r.data_source.as_deref() == Some("matchmaking_wingman")
+                } else {
+                    r.data_source.as_deref() == Some("matchmaking")
+                        && r.r#type.as_deref() == Some(rank_type)
+                }
+            })
+            .and_then(|r| r.skill_level)
+            .filter(|skill_level| *skill_level != 0)
+            .filter(|skill_level| rank_type != "premier" || *skill_level >= MIN_PREMIER_RATING)
+    }
+}
+
 pub async fn player_stats(settings: &Settings, username: &Username) -> Result<LeetifyMiniProfile> {
     let steamid = steamid_for_username(settings.clone(), username)
         .ok_or_else(|| eyre!(format!("No SteamID configured for user {username}")))?;
@@ -283,13 +306,13 @@ pub struct HallOfFame {
 }
 
 /// List top 10 players based on their skill level in their most recent game
-pub async fn hall_of_fame(settings: &Settings, rank_type: &String) -> Result<HallOfFame> {
+pub async fn hall_of_fame(settings: &Settings, rank_type: &str) -> Result<HallOfFame> {
     let steamid_mappings = settings.players.steamid_mappings.clone();
 
     let futures: Vec<_> = steamid_mappings
         .into_iter()
         .map(|(username, steamid)| {
-            let rank_type = rank_type.clone();
+            let rank_type = rank_type.to_owned();
 
             async move {
                 let resp = get_leetify_mini_profile(steamid.clone()).await;
@@ -300,17 +323,7 @@ pub async fn hall_of_fame(settings: &Settings, rank_type: &String) -> Result<Hal
                     return None;
                 };
 
-                let leetify_rank = resp.ranks.iter().find(|r| {
-                    if rank_type == "wingman" {
-                        r.data_source.as_deref() == Some("matchmaking_wingman")
-                    } else {
-                        r.data_source.as_deref() == Some("matchmaking")
-                            && r.r#type.as_ref() == Some(&rank_type)
-                    }
-                });
-                let skill_level = leetify_rank.and_then(|r| r.skill_level);
-
-                let Some(skill_level) = skill_level else {
+                let Some(skill_level) = resp.skill_level(&rank_type) else {
                     eprintln!("Failed to find {rank_type} rank for player {username}");
 
                     return None;
@@ -333,14 +346,6 @@ pub async fn hall_of_fame(settings: &Settings, rank_type: &String) -> Result<Hal
 
     let mut entries: Vec<HallOfFameEntry> = tasks_results.into_iter().flatten().collect();
 
-    // Don't include players with no rank
-    entries.retain(|entry| entry.skill_level != 0);
-
-    if rank_type == "premier" {
-        // Don't include players with old CSGO premier rank
-        entries.retain(|entry| entry.skill_level >= 1000);
-    }
-
     entries.sort_by_key(|entry| entry.skill_level);
     entries.reverse();
 
@@ -445,3 +450,103 @@ pub async fn stat_leaderboard(settings: &Settings, stat_type: &str) -> Result<St
         median,
     })
 }
+
+/// Checks which players finished a game together with at least one of the
+/// other players between `from` and `to`, in the same order as the players.
+/// Players without a configured SteamID or Leetify stats get None.
+pub async fn played_together(
+    settings: &Settings,
+    usernames: &[Username],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<Option<bool>> {
+    let steamids: Vec<Option<SteamID>> = usernames
+        .iter()
+        .map(|username| steamid_for_username(settings.clone(), username))
+        .collect();
+
+    let futures: Vec<_> = steamids
+        .iter()
+        .map(|steamid| async {
+            let steamid = steamid.as_ref()?;
+
+            // Games can't be checked without someone to play them with.
+            if !steamids.iter().flatten().any(|other| other != steamid) {
+                return None;
+            }
+
+            let resp = get_leetify_stats(steamid.clone()).await?;
+
+            let games = resp.get("games")?.clone();
+            let games = unwrap_or_log(
+                serde_json::from_value::<Vec<LeetifyGame>>(games),
+                "Error while parsing Leetify games",
+            )?;
+
+            let played = games.iter().any(|game| {
+                let in_window = game.game_finished_at >= from && game.game_finished_at <= to;
+                let with_others = steamids
+                    .iter()
+                    .flatten()
+                    .any(|other| other != steamid && game.own_team_steam64_ids.contains(other));
+
+                in_window && with_others
+            });
+
+            Some(played)
+        })
+        .collect();
+
+    // Results need to stay in the same order as the players.
+    let stream = futures::stream::iter(futures).buffered(3);
+
+    stream.collect::<Vec<_>>().await
+}
+
+/// Rating used for balancing teams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TeamRating {
+    /// Premier rating, e.g. 15000.
+    #[default]
+    Premier,
+
+    /// Leetify rating, e.g. 0.05.
+    Leetify,
+}
+
+/// Fetches given rating for each player, in the same order. Players without a
+/// configured SteamID or a rating get None.
+pub async fn team_ratings(
+    settings: &Settings,
+    usernames: &[Username],
+    rating: TeamRating,
+) -> Vec<Option<f32>> {
+    let futures: Vec<_> = usernames
+        .iter()
+        .map(|username| {
+            let steamid = steamid_for_username(settings.clone(), username);
+
+            async move {
+                let resp = get_leetify_mini_profile(steamid?).await;
+
+                let Some(resp) = resp else {
+                    eprintln!("Failed to fetch Leetify mini profile for player {username}");
+
+                    return None;
+                };
+
+                match rating {
+                    TeamRating::Premier => resp
+                        .skill_level("premier")
+                        .map(|skill_level| skill_level as f32),
+                    TeamRating::Leetify => Some(resp.ratings.leetify),
+                }
+            }
+        })
+        .collect();
+
+    // Unlike above, results need to stay in the same order as the players.
+    let stream = futures::stream::iter(futures).buffered(3);
+
+    stream.collect::<Vec<_>>().await
+}
@@ -2,17 +2,73 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
-use crate::types::{SteamID, Username};
+use teloxide::types::UserId;
+
+use crate::types::{Player, SteamID, Username};
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct TeloxideSettings {
     pub bot_api_token: String,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+/// A `steamid_mappings` entry: either just a SteamID, or a table that also
+/// contains the player's Telegram user id.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SteamIdMapping {
+    SteamId(SteamID),
+    Player { steamid: SteamID, user_id: UserId },
+}
 
+#[derive(Deserialize)]
+struct PlayersSettingsRepr {
+    steamid_mappings: HashMap<Username, SteamIdMapping>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(from = "PlayersSettingsRepr")]
 pub struct PlayersSettings {
     pub steamid_mappings: HashMap<Username, SteamID>,
+
+    /// Telegram user ids of players that have one configured, mapped to
+    /// their `steamid_mappings` key.
+    pub user_ids: HashMap<UserId, Username>,
+}
+
+impl From<PlayersSettingsRepr> for PlayersSettings {
+    fn from(repr: PlayersSettingsRepr) -> Self {
+        let mut steamid_mappings = HashMap::new();
+        let mut user_ids = HashMap::new();
+
+        for (username, mapping) in repr.steamid_mappings {
+            let steamid = match mapping {
+                SteamIdMapping::SteamId(steamid) => steamid,
+                SteamIdMapping::Player { steamid, user_id } => {
+                    user_ids.insert(user_id, username.clone());
+                    steamid
+                }
+            };
+
+            steamid_mappings.insert(username, steamid);
+        }
+
+        PlayersSettings {
+            steamid_mappings,
+            user_ids,
+        }
+    }
+}
+
+impl PlayersSettings {
+    /// Returns the `steamid_mappings` key of a player. Players are looked up
+    /// by Telegram user id when one is configured, and by name otherwise.
+    pub fn mapping_username(&self, player: &Player) -> Username {
+        player
+            .id
+            .and_then(|id| self.user_ids.get(&id))
+            .unwrap_or(&player.name)
+            .clone()
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -25,6 +81,119 @@ pub struct WeatherSettings {
     pub display_name: String,
 }
 
+/// What to do with queues that timed out while the bot was offline.
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTimeouts {
+    /// Announce the queues as usual, noting that the announcement is late.
+    #[default]
+    Announce,
+
+    /// Remove the queues without pinging anyone.
+    Expire,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct QueueSettings {
+    /// How many hours before each occurrence recurring queues are opened.
+    pub recurring_lead_time_hours: i64,
+
+    /// Post a new status message when a queue fills up, instead of editing
+    /// the queue's existing status message.
+    pub repost_when_full: bool,
+
+    /// Minutes before a timed queue starts when players are reminded of it.
+    pub reminder_minutes: Vec<i64>,
+
+    pub missed_timeouts: MissedTimeouts,
+
+    /// Minutes players have to confirm they're ready once a queue is ready to
+    /// play. Zero disables ready checks.
+    pub ready_check_minutes: i64,
+
+    /// Users who can moderate queues in every chat, in addition to chat
+    /// administrators.
+    pub admins: Vec<UserId>,
+
+    /// Start a captain draft when a queue of ten or more players is ready to
+    /// play.
+    pub captain_draft: bool,
+
+    /// Maps in the map veto (/veto).
+    pub map_pool: Vec<String>,
+
+    /// Hours after a played queue starts during which its players should
+    /// have finished a game together on Leetify. Players who didn't are
+    /// counted as no-shows once the window has passed. Zero disables the
+    /// check.
+    pub no_show_check_hours: i64,
+
+    /// How long a match lasts in minutes. Availability ranges (/avail) only
+    /// count toward a start time if they cover a whole match from it.
+    pub match_minutes: i64,
+}
+
+/// Competitive map pool, used as the default map veto pool.
+const ACTIVE_DUTY_MAPS: [&str; 7] = [
+    "de_ancient",
+    "de_anubis",
+    "de_dust2",
+    "de_inferno",
+    "de_mirage",
+    "de_nuke",
+    "de_train",
+];
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        QueueSettings {
+            recurring_lead_time_hours: 24,
+            repost_when_full: false,
+            reminder_minutes: vec![30, 10],
+            missed_timeouts: MissedTimeouts::Announce,
+            ready_check_minutes: 5,
+            admins: vec![],
+            captain_draft: true,
+            map_pool: ACTIVE_DUTY_MAPS.iter().map(|map| map.to_string()).collect(),
+            no_show_check_hours: 4,
+            match_minutes: 60,
+        }
+    }
+}
+
+/// Where persistent state is stored.
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// A single JSON file.
+    #[default]
+    Json,
+
+    /// An embedded SQLite database.
+    Sqlite,
+}
+
+impl StorageBackend {
+    /// Returns the default path of the backend's state file.
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            StorageBackend::Json => "state.json",
+            StorageBackend::Sqlite => "state.db",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+
+    /// Path of the state file, relative to CWD. Defaults to `state.json` or
+    /// `state.db` depending on the backend.
+    pub path: Option<String>,
+}
+
 #[derive(Clone, Deserialize, Debug)]
 
 pub struct Settings {
@@ -33,6 +202,12 @@ pub struct Settings {
     pub players: PlayersSettings,
 
     pub weather: Option<WeatherSettings>,
+
+    #[serde(default)]
+    pub queue: QueueSettings,
+
+    #[serde(default)]
+    pub storage: StorageSettings,
 }
 
 pub fn read_settings() -> Result<Settings, config::ConfigError> {
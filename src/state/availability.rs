@@ -0,0 +1,224 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use super::{Chat, JoinMode, Queue, State};
+use crate::types::{Player, QueueId};
+
+/// Time range when a player is available to play, used to find a start time
+/// that suits enough players.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct Availability {
+    pub player: Player,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Availability {
+    /// Returns whether the player is available for `length` from `t`.
+    pub fn covers(&self, t: DateTime<Utc>, length: Duration) -> bool {
+        self.from <= t && t + length <= self.to
+    }
+}
+
+impl Chat {
+    /// Finds the earliest start time after `now` when at least `size` players
+    /// are available for a match of given `length`. If there's no such time,
+    /// the earliest time with the most available players is returned instead.
+    ///
+    /// Ranges that have already begun are considered from the next full
+    /// quarter hour.
+    ///
+    /// Returns the start time and available players in posting order, or None
+    /// if nobody is available after `now`.
+    pub fn best_availability_slot(
+        &self,
+        size: usize,
+        length: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<(DateTime<Utc>, Vec<Player>)> {
+        let step = Duration::minutes(15);
+        let earliest = now.duration_trunc(step).map_or(now, |t| t + step);
+
+        // The number of available players only grows at the start of a range,
+        // so those are the only candidates. Ranges too short for a match
+        // never count.
+        let mut candidates: Vec<DateTime<Utc>> = self
+            .availability
+            .iter()
+            .map(|availability| {
+                if availability.from > now {
+                    availability.from
+                } else {
+                    earliest
+                }
+            })
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        let mut best: Option<(DateTime<Utc>, Vec<Player>)> = None;
+
+        for t in candidates {
+            let players: Vec<Player> = self
+                .availability
+                .iter()
+                .filter(|availability| availability.covers(t, length))
+                .map(|availability| availability.player.clone())
+                .collect();
+
+            if players.len() >= size {
+                return Some((t, players));
+            }
+
+            if players.len() > best.as_ref().map_or(0, |(_, best)| best.len()) {
+                best = Some((t, players));
+            }
+        }
+
+        best
+    }
+}
+
+impl State {
+    /// Sets player's availability in given chat, replacing any earlier range
+    /// of theirs. Ranges that have ended by `now` are dropped.
+    pub fn set_availability(
+        &self,
+        chat_id: &ChatId,
+        availability: Availability,
+        now: DateTime<Utc>,
+    ) -> State {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        chat.availability
+            .retain(|existing| existing.player != availability.player && existing.to > now);
+        chat.availability.push(availability);
+
+        state
+    }
+
+    /// Removes player's availability from given chat.
+    ///
+    /// Returns None if the player has no availability posted.
+    pub fn rm_availability(&self, chat_id: &ChatId, player: &Player) -> Option<State> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let index = chat
+            .availability
+            .iter()
+            .position(|availability| availability.player == *player)?;
+        chat.availability.remove(index);
+
+        Some(state)
+    }
+
+    /// Adds players to a timed queue at a start time found from their
+    /// availability, and removes their availability ranges. If the queue
+    /// already exists, the players are merged into it.
+    ///
+    /// Returns a tuple of new State and the queue.
+    pub fn open_availability_queue(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        add_cmd: String,
+        timeout: DateTime<Utc>,
+        players: &[Player],
+    ) -> (State, Queue) {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        let chat_queue_size = chat.queue_size();
+        let auto_players = chat.auto_add_players(queue_id);
+
+        chat.availability
+            .retain(|availability| !players.contains(&availability.player));
+
+        let queue = chat.queues.entry(queue_id.clone()).or_insert_with(|| {
+            let mut queue = Queue::new(timeout, add_cmd, chat_queue_size);
+            for player in auto_players {
+                if !players.contains(&player) {
+                    queue.insert_auto_player(player);
+                }
+            }
+            queue
+        });
+
+        for player in players {
+            if queue.player_tier(player) != Some(JoinMode::Yes) {
+                queue.remove_player(player);
+                queue.insert_player(player.clone());
+            }
+        }
+
+        let queue = queue.clone();
+
+        (state, queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_util::players;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn finds_earliest_slot_with_enough_available_players() {
+        let at = |s| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+                .unwrap()
+                .and_utc()
+        };
+        let available = |player, from, to| Availability {
+            player,
+            from: at(from),
+            to: at(to),
+        };
+
+        let hour = Duration::hours(1);
+        let players = players(3);
+        let (alice, bob, carol) = (&players[0], &players[1], &players[2]);
+
+        let chat = Chat {
+            availability: vec![
+                available(alice.clone(), "2024-12-24 19:00", "2024-12-24 22:30"),
+                available(bob.clone(), "2024-12-24 20:00", "2024-12-24 23:00"),
+                available(carol.clone(), "2024-12-24 18:00", "2024-12-24 20:00"),
+            ],
+            ..Default::default()
+        };
+
+        let now = at("2024-12-24 12:00");
+        let slot = chat.best_availability_slot(2, hour, now);
+        assert_eq!(
+            slot,
+            Some((at("2024-12-24 19:00"), vec![alice.clone(), carol.clone()]))
+        );
+
+        // Carol's range ends before a longer match would.
+        let slot = chat.best_availability_slot(2, Duration::minutes(90), now);
+        assert_eq!(
+            slot,
+            Some((at("2024-12-24 20:00"), vec![alice.clone(), bob.clone()]))
+        );
+
+        // Without enough players, the slot with the most players is returned.
+        let slot = chat.best_availability_slot(3, hour, now);
+        assert_eq!(
+            slot.map(|(t, players)| (t, players.len())),
+            Some((at("2024-12-24 19:00"), 2))
+        );
+
+        // Ranges already underway start from the next quarter hour.
+        let now = at("2024-12-24 20:05");
+        let slot = chat.best_availability_slot(2, hour, now);
+        assert_eq!(
+            slot,
+            Some((at("2024-12-24 20:15"), vec![alice.clone(), bob.clone()]))
+        );
+    }
+}
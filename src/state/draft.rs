@@ -0,0 +1,205 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId};
+
+use super::State;
+use crate::types::{Player, QueueId};
+
+/// How long a captain draft can go on before it's considered abandoned.
+const DRAFT_TIMEOUT_MINUTES: i64 = 60;
+
+/// Captain draft of two teams, where captains take turns picking players.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct Draft {
+    /// Players in the draft along with their Leetify ratings.
+    pub players: Vec<(Player, Option<f32>)>,
+
+    /// Picked players of both teams as indices into `players`, captains
+    /// first.
+    pub teams: [Vec<usize>; 2],
+
+    /// Telegram message showing the draft.
+    pub msg_id: Option<MessageId>,
+
+    pub created_at: DateTime<Utc>,
+}
+
+impl Draft {
+    pub fn new(players: Vec<(Player, Option<f32>)>, captains: [usize; 2]) -> Draft {
+        Draft {
+            players,
+            teams: [vec![captains[0]], vec![captains[1]]],
+            msg_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Returns true if the draft was started too long ago to still be going
+    /// on.
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        now - self.created_at > Duration::minutes(DRAFT_TIMEOUT_MINUTES)
+    }
+
+    /// Returns indices of players who haven't been picked yet.
+    pub fn remaining(&self) -> Vec<usize> {
+        (0..self.players.len())
+            .filter(|i| !self.teams.iter().any(|team| team.contains(i)))
+            .collect()
+    }
+
+    /// Returns the team whose captain picks next. Captains alternate, starting
+    /// with the first captain.
+    pub fn picking_team(&self) -> usize {
+        let picks = self.teams[0].len() + self.teams[1].len() - 2;
+        picks % 2
+    }
+
+    /// Returns the captain of given team.
+    pub fn captain(&self, team: usize) -> &Player {
+        &self.players[self.teams[team][0]].0
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.remaining().is_empty()
+    }
+
+    /// Adds a remaining player to the team whose turn it is. The last
+    /// remaining player goes to the other team without a pick.
+    ///
+    /// Returns false if the player was already picked.
+    pub fn pick(&mut self, index: usize) -> bool {
+        if !self.remaining().contains(&index) {
+            return false;
+        }
+
+        self.teams[self.picking_team()].push(index);
+
+        if let [last] = self.remaining()[..] {
+            self.teams[self.picking_team()].push(last);
+        }
+
+        true
+    }
+}
+
+impl State {
+    /// Starts a captain draft for given queue, replacing any earlier draft
+    /// for it. Stale drafts of the chat are dropped.
+    pub fn start_draft(&self, chat_id: &ChatId, queue_id: &QueueId, draft: Draft) -> State {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        chat.drafts
+            .retain(|_, other| !other.is_stale(draft.created_at));
+        chat.drafts.insert(queue_id.clone(), draft);
+
+        state
+    }
+
+    /// Sets the Telegram message showing a captain draft.
+    pub fn set_draft_msg(&self, chat_id: &ChatId, queue_id: &QueueId, msg_id: MessageId) -> State {
+        let mut state = self.clone();
+
+        if let Some(draft) = state
+            .chats
+            .get_mut(chat_id)
+            .and_then(|chat| chat.drafts.get_mut(queue_id))
+        {
+            draft.msg_id = Some(msg_id);
+        }
+
+        state
+    }
+
+    /// Picks a player to a team in a captain draft on behalf of `captain`.
+    /// Finished drafts are removed.
+    ///
+    /// Returns a tuple of new State and the draft, or None if there's no such
+    /// draft, it's stale, it isn't the captain's turn or the player was already
+    /// picked.
+    pub fn pick_draft_player(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        captain: &Player,
+        index: usize,
+    ) -> Option<(State, Draft)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let draft = chat.drafts.get_mut(queue_id)?;
+
+        if draft.is_stale(Utc::now()) {
+            return None;
+        }
+
+        if draft.captain(draft.picking_team()) != captain || !draft.pick(index) {
+            return None;
+        }
+
+        let draft = draft.clone();
+        if draft.is_finished() {
+            chat.drafts.remove(queue_id);
+        }
+
+        Some((state, draft))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_util::{players, CHAT_ID};
+
+    #[test]
+    fn captains_alternate_draft_picks() {
+        let queue_id = QueueId::instant();
+        let players = players(4);
+
+        let draft = Draft::new(
+            players
+                .iter()
+                .map(|player| (player.clone(), None))
+                .collect(),
+            [0, 1],
+        );
+        let state = State::default().start_draft(&CHAT_ID, &queue_id, draft);
+
+        // Only the captain whose turn it is can pick.
+        assert!(state
+            .pick_draft_player(&CHAT_ID, &queue_id, &players[1], 2)
+            .is_none());
+
+        let (state, draft) = state
+            .pick_draft_player(&CHAT_ID, &queue_id, &players[0], 3)
+            .unwrap();
+
+        // The last remaining player goes to the other team, ending the draft.
+        assert_eq!(draft.teams, [vec![0, 3], vec![1, 2]]);
+        assert!(draft.is_finished());
+        assert!(state.chats[&CHAT_ID].drafts.is_empty());
+    }
+
+    #[test]
+    fn captains_added_by_name_can_pick_once_identified() {
+        let queue_id = QueueId::instant();
+        let players = players(4);
+        let by_name = Player::new(None, players[0].name.clone());
+
+        let mut entries: Vec<_> = players
+            .iter()
+            .map(|player| (player.clone(), None))
+            .collect();
+        entries[0].0 = by_name;
+
+        let state = State::default().start_draft(&CHAT_ID, &queue_id, Draft::new(entries, [0, 1]));
+        assert!(state
+            .pick_draft_player(&CHAT_ID, &queue_id, &players[0], 3)
+            .is_none());
+
+        let state = state.identify_player(&players[0]).unwrap();
+        assert!(state
+            .pick_draft_player(&CHAT_ID, &queue_id, &players[0], 3)
+            .is_some());
+    }
+}
@@ -0,0 +1,1917 @@
+use crate::types::{Player, QueueId, Username};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Utc, Weekday};
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use teloxide::types::{ChatId, MessageId, UserId};
+
+mod availability;
+mod draft;
+#[cfg(test)]
+pub mod test_util;
+mod veto;
+
+pub use availability::Availability;
+pub use draft::Draft;
+pub use veto::{Veto, VetoStep};
+
+/// Queue size used when neither the queue nor the chat specifies one.
+pub const DEFAULT_QUEUE_SIZE: usize = 5;
+
+/// Largest accepted queue size.
+pub const MAX_QUEUE_SIZE: usize = 32;
+
+/// Number of finished queues kept in each chat's queue history.
+const MAX_QUEUE_HISTORY: usize = 1000;
+
+/// Number of times attendance of a played queue is checked before giving up,
+/// in case Leetify hasn't processed the games yet.
+pub const MAX_ATTENDANCE_CHECKS: u32 = 3;
+
+fn default_queue_size() -> usize {
+    DEFAULT_QUEUE_SIZE
+}
+
+/// Creation time of queues from state files that didn't record it. Such
+/// queues are treated as created long ago, so that their reminders are sent.
+fn legacy_created_at() -> DateTime<Utc> {
+    DateTime::UNIX_EPOCH
+}
+
+/// Ready check of a queue that is ready to play, where players in the lineup
+/// confirm that they're still around.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct ReadyCheck {
+    pub deadline: DateTime<Utc>,
+    pub confirmed: Vec<Player>,
+}
+
+/// Contains the set of players who have added up to a queue, along with a
+/// timeout for when the queue expires.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct Queue {
+    players: IndexSet<Player>,
+    pub timeout: DateTime<Utc>,
+    pub add_cmd: String,
+    #[serde(default = "default_queue_size")]
+    size: usize,
+
+    /// Latest Telegram message showing the status of this queue.
+    #[serde(default)]
+    pub status_msg_id: Option<MessageId>,
+
+    #[serde(default = "legacy_created_at")]
+    pub created_at: DateTime<Utc>,
+
+    /// Reminders (in minutes before timeout) that have already been sent.
+    #[serde(default)]
+    pub sent_reminders: Vec<i64>,
+
+    #[serde(default)]
+    pub ready_check: Option<ReadyCheck>,
+
+    /// Players who only play if someone in the lineup drops out.
+    #[serde(default)]
+    reserve_only: Vec<Player>,
+
+    /// Players who might play. They don't count toward filling the queue.
+    #[serde(default)]
+    maybe: IndexSet<Player>,
+
+    /// Players who were added by someone else, along with who added them.
+    #[serde(default)]
+    added_by: Vec<(Player, UserId)>,
+
+    /// Subscribers who have been alerted about this queue.
+    #[serde(default)]
+    alerted: Vec<Player>,
+
+    /// Players who were added by their availability rules.
+    #[serde(default)]
+    auto_added: Vec<Player>,
+
+    /// Number of times teams have been rerolled since they were last balanced.
+    #[serde(default)]
+    teams_rerolls: usize,
+
+    /// Players who left the queue while it was full.
+    #[serde(default)]
+    left_after_full: Vec<Player>,
+}
+
+impl Queue {
+    pub fn new(timeout: DateTime<Utc>, add_cmd: String, size: usize) -> Queue {
+        Queue {
+            timeout,
+            players: Default::default(),
+            add_cmd,
+            size,
+            status_msg_id: None,
+            created_at: Utc::now(),
+            sent_reminders: vec![],
+            ready_check: None,
+            reserve_only: vec![],
+            maybe: Default::default(),
+            added_by: vec![],
+            alerted: vec![],
+            auto_added: vec![],
+            teams_rerolls: 0,
+            left_after_full: vec![],
+        }
+    }
+
+    /// Return whether queue has players or not, including maybe players.
+    pub fn has_players(&self) -> bool {
+        !self.players.is_empty() || !self.maybe.is_empty()
+    }
+
+    /// Return number of players in queue, not counting reserve-only players.
+    pub fn num_players(&self) -> usize {
+        self.players.len() - self.reserve_only.len()
+    }
+
+    /// Return whether queue is full or not.
+    pub fn is_full(&self) -> bool {
+        self.num_players() >= self.size
+    }
+
+    /// Returns lists of players split into players and reserve players.
+    /// Reserve-only players are always listed last in the reserve.
+    pub fn get_players(&self) -> (Vec<Player>, Option<Vec<Player>>) {
+        let (mut players, reserve_only): (Vec<Player>, Vec<Player>) = self
+            .players
+            .iter()
+            .cloned()
+            .partition(|player| !self.reserve_only.contains(player));
+
+        // Split full queues into players and reserve players.
+        let mut reserve = if players.len() > self.size {
+            players.split_off(self.size)
+        } else {
+            vec![]
+        };
+        reserve.extend(reserve_only);
+
+        if reserve.is_empty() {
+            (players, None)
+        } else {
+            (players, Some(reserve))
+        }
+    }
+
+    /// Returns players who might play.
+    pub fn get_maybe_players(&self) -> Vec<Player> {
+        self.maybe.iter().cloned().collect()
+    }
+
+    /// Returns size of this queue.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Changes size of this queue.
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size;
+    }
+
+    /// Returns which tier of the queue player is in, if any.
+    pub fn player_tier(&self, player: &Player) -> Option<JoinMode> {
+        if self.reserve_only.contains(player) {
+            Some(JoinMode::Reserve)
+        } else if self.players.contains(player) {
+            Some(JoinMode::Yes)
+        } else if self.maybe.contains(player) {
+            Some(JoinMode::Maybe)
+        } else {
+            None
+        }
+    }
+
+    /// Insert player, upgrading them if they're a maybe player.
+    pub fn insert_player(&mut self, player: Player) {
+        self.maybe.shift_remove(&player);
+        self.players.insert(player);
+    }
+
+    /// Insert player who was added by their availability rules.
+    pub fn insert_auto_player(&mut self, player: Player) {
+        if self.players.insert(player.clone()) {
+            self.auto_added.push(player);
+        }
+    }
+
+    /// Return whether player was added by their availability rules.
+    pub fn is_auto_added(&self, player: &Player) -> bool {
+        self.auto_added.contains(player)
+    }
+
+    /// Insert player who only plays if someone in the lineup drops out.
+    pub fn insert_reserve_player(&mut self, player: Player) {
+        if self.players.insert(player.clone()) {
+            self.reserve_only.push(player);
+        }
+    }
+
+    /// Insert player who might play.
+    pub fn insert_maybe_player(&mut self, player: Player) {
+        if !self.players.contains(&player) {
+            self.maybe.insert(player);
+        }
+    }
+
+    /// Remove player. If the player leaves a full lineup with no other
+    /// reserve players, the first reserve-only player takes their place.
+    ///
+    /// Cancels an ongoing ready check if the queue is no longer full.
+    ///
+    /// Returns whether the player was in queue.
+    pub fn remove_player(&mut self, player: &Player) -> bool {
+        let was_full = self.is_full();
+
+        let removed = self.players.shift_remove(player) | self.maybe.shift_remove(player);
+        self.reserve_only.retain(|reserve| reserve != player);
+        self.added_by.retain(|(added, _)| added != player);
+        self.auto_added.retain(|auto| auto != player);
+
+        if let Some(ready_check) = &mut self.ready_check {
+            ready_check
+                .confirmed
+                .retain(|confirmed| confirmed != player);
+        }
+
+        if was_full && !self.is_full() && !self.reserve_only.is_empty() {
+            self.reserve_only.remove(0);
+        }
+
+        if !self.is_full() {
+            self.ready_check = None;
+        }
+
+        removed
+    }
+
+    /// Removes a player who leaves the queue, unlike `remove_player` which is
+    /// also used to move players between tiers. Players leaving the lineup of
+    /// a full queue are kept in `left_after_full`.
+    ///
+    /// Returns whether the player was in queue.
+    pub fn leave(&mut self, player: &Player) -> bool {
+        let (lineup, _) = self.get_players();
+
+        if self.is_full() && lineup.contains(player) && !self.left_after_full.contains(player) {
+            self.left_after_full.push(player.clone());
+        }
+
+        self.remove_player(player)
+    }
+
+    /// Returns who added player to the queue, if it was someone else.
+    pub fn added_by(&self, player: &Player) -> Option<UserId> {
+        self.added_by
+            .iter()
+            .find(|(added, _)| added == player)
+            .map(|(_, user_id)| *user_id)
+    }
+
+    /// Adds players of another queue after this queue's players, keeping
+    /// their order and tiers, along with who added them, whether they were
+    /// auto-added and their ready check confirmations. Players already in this
+    /// queue keep their entries as is.
+    pub fn merge(&mut self, other: &Queue) {
+        let known: Vec<Player> = self.players.iter().chain(&self.maybe).cloned().collect();
+
+        for player in &other.players {
+            if other.reserve_only.contains(player) {
+                self.insert_reserve_player(player.clone());
+            } else {
+                self.insert_player(player.clone());
+            }
+        }
+
+        for player in &other.maybe {
+            self.insert_maybe_player(player.clone());
+        }
+
+        for (player, user_id) in &other.added_by {
+            if !known.contains(player) {
+                self.added_by.push((player.clone(), *user_id));
+            }
+        }
+
+        for player in &other.auto_added {
+            if !known.contains(player) {
+                self.auto_added.push(player.clone());
+            }
+        }
+
+        for player in &other.left_after_full {
+            if !self.left_after_full.contains(player) {
+                self.left_after_full.push(player.clone());
+            }
+        }
+
+        for player in &other.alerted {
+            if !self.alerted.contains(player) {
+                self.alerted.push(player.clone());
+            }
+        }
+
+        if let (Some(ready_check), Some(other_check)) = (&mut self.ready_check, &other.ready_check)
+        {
+            for player in &other_check.confirmed {
+                if !ready_check.confirmed.contains(player) {
+                    ready_check.confirmed.push(player.clone());
+                }
+            }
+        }
+    }
+
+    /// Returns players in the lineup who haven't confirmed an ongoing ready
+    /// check.
+    pub fn unconfirmed_players(&self) -> Vec<Player> {
+        let Some(ready_check) = &self.ready_check else {
+            return vec![];
+        };
+
+        let (players, _) = self.get_players();
+
+        players
+            .into_iter()
+            .filter(|player| !ready_check.confirmed.contains(player))
+            .collect()
+    }
+
+    /// Returns whether everyone in the lineup has confirmed the ready check.
+    pub fn is_ready(&self) -> bool {
+        self.ready_check.is_some() && self.is_full() && self.unconfirmed_players().is_empty()
+    }
+}
+
+/// Returns whether `entry` refers to the identified `player`, either by user
+/// id or by name if the entry has no user id.
+fn refers_to(entry: &Player, player: &Player) -> bool {
+    entry.id == player.id || (entry.id.is_none() && entry.name == player.name)
+}
+
+/// Updates entries referring to the identified `player` with the player's
+/// user id and current name, keeping order and dropping duplicates.
+///
+/// Returns None if no entries needed updating.
+fn identify_in<'a, T>(players: &'a T, player: &Player) -> Option<T>
+where
+    &'a T: IntoIterator<Item = &'a Player>,
+    T: FromIterator<Player>,
+{
+    let changed = players.into_iter().any(|entry| {
+        refers_to(entry, player) && (entry.id != player.id || entry.name != player.name)
+    });
+
+    if !changed {
+        return None;
+    }
+
+    let mut seen = IndexSet::new();
+
+    let players = players
+        .into_iter()
+        .map(|entry| {
+            if refers_to(entry, player) {
+                player.clone()
+            } else {
+                entry.clone()
+            }
+        })
+        .filter(|entry| seen.insert(entry.clone()))
+        .collect();
+
+    Some(players)
+}
+
+/// Returns players in the lineup of `queue` who weren't in the `previous`
+/// lineup, i.e. who were promoted from the reserve.
+fn promoted_players(previous: &[Player], queue: &Queue) -> Vec<Player> {
+    let (players, _) = queue.get_players();
+
+    players
+        .into_iter()
+        .filter(|player| !previous.contains(player))
+        .collect()
+}
+
+/// Definition of a queue that is opened automatically every week on the
+/// given weekdays.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct RecurringQueue {
+    pub id: u32,
+    pub weekdays: Vec<Weekday>,
+    pub time: NaiveTime,
+
+    /// Players that are added to each opened queue.
+    pub players: Vec<Player>,
+    pub size: Option<usize>,
+    pub paused: bool,
+
+    /// Start time of the most recently opened queue.
+    pub last_opened: Option<DateTime<Utc>>,
+}
+
+/// Alert for a player when a queue in the chat reaches given number of
+/// players.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct Subscription {
+    pub player: Player,
+    pub min_players: usize,
+
+    /// Only alert about queues starting within this time range.
+    pub window: Option<(NaiveTime, NaiveTime)>,
+
+    /// Alert by direct message instead of a mention in the chat.
+    pub dm: bool,
+}
+
+impl Subscription {
+    /// Returns whether a queue starting at `start` (local time) is within the
+    /// subscription's time range.
+    pub fn in_window(&self, start: NaiveTime) -> bool {
+        self.window
+            .is_none_or(|(from, to)| in_time_range(from, to, start))
+    }
+}
+
+/// Returns whether `t` is within given time range. Ranges can wrap past
+/// midnight.
+fn in_time_range(from: NaiveTime, to: NaiveTime, t: NaiveTime) -> bool {
+    if from <= to {
+        from <= t && t <= to
+    } else {
+        t >= from || t <= to
+    }
+}
+
+/// Player's standing availability, for adding them automatically to timed
+/// queues starting on given weekdays within given time range.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct AutoAddRule {
+    pub id: u32,
+    pub player: Player,
+    pub weekdays: Vec<Weekday>,
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+}
+
+impl AutoAddRule {
+    /// Returns whether a queue starting at `start` (local time) matches the
+    /// rule.
+    pub fn matches(&self, start: NaiveDateTime) -> bool {
+        self.weekdays.contains(&start.weekday()) && in_time_range(self.from, self.to, start.time())
+    }
+}
+
+/// Finished queue, kept in the chat's queue history.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct QueueRecord {
+    pub queue_id: QueueId,
+
+    /// When the queue was set to start. Instant queues start when they
+    /// finish.
+    pub start: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub lineup: Vec<Player>,
+    pub reserve: Vec<Player>,
+
+    /// Whether the queue filled up and was played.
+    pub filled: bool,
+
+    /// Players who left the queue while it was full.
+    pub left_after_full: Vec<Player>,
+
+    /// Which players of the lineup showed up, once checked.
+    #[serde(default)]
+    pub attendance: Option<Attendance>,
+
+    /// How many more times attendance may be checked. Queues recorded before
+    /// attendance was checked default to none.
+    #[serde(default)]
+    pub attendance_checks_left: u32,
+
+    /// Map veto of a played queue.
+    #[serde(default)]
+    pub veto: Option<Veto>,
+}
+
+/// Players of a played queue who showed up to play, and who didn't. Players
+/// whose games couldn't be checked are in neither list.
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Attendance {
+    pub showed_up: Vec<Player>,
+    pub no_shows: Vec<Player>,
+}
+
+impl QueueRecord {
+    fn new(queue_id: &QueueId, queue: &Queue, filled: bool) -> QueueRecord {
+        let finished_at = Utc::now();
+        let (lineup, reserve) = queue.get_players();
+
+        QueueRecord {
+            queue_id: queue_id.clone(),
+            start: if queue_id.is_instant_queue() {
+                finished_at
+            } else {
+                queue.timeout
+            },
+            finished_at,
+            lineup,
+            reserve: reserve.unwrap_or_default(),
+            filled,
+            left_after_full: queue.left_after_full.clone(),
+            attendance: None,
+            attendance_checks_left: if filled { MAX_ATTENDANCE_CHECKS } else { 0 },
+            veto: None,
+        }
+    }
+}
+
+/// A chat separates queues by Telegram groups.
+#[derive(Clone, Deserialize, Serialize, Default)]
+pub struct Chat {
+    pub queues: HashMap<QueueId, Queue>,
+
+    /// Size of new queues in this chat, unless overridden per queue.
+    #[serde(default)]
+    pub queue_size: Option<usize>,
+
+    #[serde(default)]
+    pub recurring_queues: Vec<RecurringQueue>,
+
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+
+    #[serde(default)]
+    pub auto_add_rules: Vec<AutoAddRule>,
+
+    /// Posted availability ranges, at most one per player, in posting order.
+    #[serde(default)]
+    pub availability: Vec<Availability>,
+
+    /// Ongoing captain drafts by the queue they were started for.
+    #[serde(default)]
+    pub drafts: HashMap<QueueId, Draft>,
+
+    /// Queues that were played or timed out, oldest first.
+    #[serde(default)]
+    pub history: Vec<QueueRecord>,
+}
+
+impl Chat {
+    /// Adds a finished queue to the queue history, forgetting the oldest
+    /// queues once the history is full.
+    fn record_queue(&mut self, queue_id: &QueueId, queue: &Queue, filled: bool) {
+        self.history.push(QueueRecord::new(queue_id, queue, filled));
+
+        let excess = self.history.len().saturating_sub(MAX_QUEUE_HISTORY);
+        self.history.drain(..excess);
+    }
+
+    /// Returns the default size of new queues in this chat.
+    pub fn queue_size(&self) -> usize {
+        self.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE)
+    }
+
+    /// Returns players whose availability rules match given timed queue.
+    fn auto_add_players(&self, queue_id: &QueueId) -> Vec<Player> {
+        let Some(start) = queue_id.local_time() else {
+            return vec![];
+        };
+
+        let mut players: Vec<Player> = vec![];
+
+        for rule in &self.auto_add_rules {
+            if rule.matches(start) && !players.contains(&rule.player) {
+                players.push(rule.player.clone());
+            }
+        }
+
+        players
+    }
+}
+
+/// How a player joins a queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JoinMode {
+    /// Adds the player, or removes them if they're already in the queue.
+    #[default]
+    Toggle,
+
+    /// Adds the player, upgrading them if they're a maybe player.
+    Yes,
+
+    /// Player only plays if someone in the lineup drops out.
+    Reserve,
+
+    /// Player might play, but doesn't count toward filling the queue.
+    Maybe,
+}
+
+/// A player's `/add` or queue button press, see `State::add_remove_player`.
+pub struct AddRemovePlayerArgs {
+    /// Command used to add players to the queue, if it's created.
+    pub add_cmd: String,
+    /// Timeout of the queue, if it's created.
+    pub timeout: DateTime<Utc>,
+    /// Size to resize the queue to.
+    pub size: Option<usize>,
+    pub player: Player,
+    pub mode: JoinMode,
+}
+
+pub enum AddRemovePlayerOp {
+    PlayerAdded(Player),
+    MaybePlayerAdded(Player),
+    PlayerRemoved(Player),
+    QueueResized(usize),
+}
+
+impl std::fmt::Display for AddRemovePlayerOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            AddRemovePlayerOp::PlayerAdded(player) => format!("Added {}", player),
+            AddRemovePlayerOp::MaybePlayerAdded(player) => format!("Added {} as maybe", player),
+            AddRemovePlayerOp::PlayerRemoved(player) => format!("Removed {}", player),
+            AddRemovePlayerOp::QueueResized(size) => format!("Queue size set to {}", size),
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+pub enum AddRemovePlayerResult {
+    QueueEmpty(Queue),
+    PlayerQueued(Queue),
+    QueueFull(Queue),
+}
+
+/// (De)Serializable state containing chats with active queues.
+#[derive(Clone, Deserialize, Serialize, Default)]
+pub struct State {
+    pub chats: HashMap<ChatId, Chat>,
+
+    /// Latest known names of Telegram users the bot has seen.
+    #[serde(default)]
+    pub users: HashMap<UserId, Username>,
+}
+
+impl State {
+    /// Records the current name of a player with a known user id, and updates
+    /// queue entries referring to the player. Entries added by name get the
+    /// player's user id.
+    ///
+    /// Returns None if nothing changed.
+    pub fn identify_player(&self, player: &Player) -> Option<State> {
+        let id = player.id?;
+        let mut state = self.clone();
+
+        let mut changed = state.users.get(&id) != Some(&player.name);
+        state.users.insert(id, player.name.clone());
+
+        for chat in state.chats.values_mut() {
+            for queue in chat.queues.values_mut() {
+                if let Some(players) = identify_in(&queue.players, player) {
+                    queue.players = players;
+                    changed = true;
+                }
+
+                if let Some(reserve_only) = identify_in(&queue.reserve_only, player) {
+                    queue.reserve_only = reserve_only;
+                    changed = true;
+                }
+
+                if let Some(maybe) = identify_in(&queue.maybe, player) {
+                    queue.maybe = maybe;
+                    changed = true;
+                }
+
+                if let Some(auto_added) = identify_in(&queue.auto_added, player) {
+                    queue.auto_added = auto_added;
+                    changed = true;
+                }
+
+                for (added, _) in queue.added_by.iter_mut() {
+                    if refers_to(added, player) && *added != *player {
+                        *added = player.clone();
+                        changed = true;
+                    }
+                }
+
+                if let Some(ready_check) = &mut queue.ready_check {
+                    if let Some(confirmed) = identify_in(&ready_check.confirmed, player) {
+                        ready_check.confirmed = confirmed;
+                        changed = true;
+                    }
+                }
+            }
+
+            for recurring in chat.recurring_queues.iter_mut() {
+                if let Some(players) = identify_in(&recurring.players, player) {
+                    recurring.players = players;
+                    changed = true;
+                }
+            }
+
+            for rule in chat.auto_add_rules.iter_mut() {
+                if refers_to(&rule.player, player) && rule.player != *player {
+                    rule.player = player.clone();
+                    changed = true;
+                }
+            }
+
+            for availability in chat.availability.iter_mut() {
+                if refers_to(&availability.player, player) && availability.player != *player {
+                    availability.player = player.clone();
+                    changed = true;
+                }
+            }
+
+            for subscription in chat.subscriptions.iter_mut() {
+                if refers_to(&subscription.player, player) && subscription.player != *player {
+                    subscription.player = player.clone();
+                    changed = true;
+                }
+            }
+
+            for draft in chat.drafts.values_mut() {
+                for (entry, _) in draft.players.iter_mut() {
+                    if refers_to(entry, player) && *entry != *player {
+                        *entry = player.clone();
+                        changed = true;
+                    }
+                }
+            }
+
+            let vetoes = chat
+                .history
+                .iter_mut()
+                .filter_map(|record| record.veto.as_mut());
+            for side in vetoes.flat_map(|veto| veto.sides.iter_mut().flatten()) {
+                if refers_to(side, player) && *side != *player {
+                    *side = player.clone();
+                    changed = true;
+                }
+            }
+        }
+
+        changed.then_some(state)
+    }
+
+    /// Returns the player known by given name, falling back to a player
+    /// without a user id if the bot hasn't seen anyone by that name.
+    ///
+    /// Returns None if several users go by the name, since there's no way to
+    /// tell which one was meant.
+    pub fn player_by_name(&self, name: Username) -> Option<Player> {
+        let mut ids = self
+            .users
+            .iter()
+            .filter(|(_, known_name)| **known_name == name)
+            .map(|(id, _)| *id);
+
+        match (ids.next(), ids.next()) {
+            (id, None) => Some(Player::new(id, name)),
+            _ => None,
+        }
+    }
+
+    /// Removes a given chat queue.
+    pub fn rm_chat_queue(&self, chat_id: &ChatId, queue_id: &QueueId) -> (State, Option<Queue>) {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id);
+        let queue = chat.and_then(|chat| chat.queues.remove(queue_id));
+
+        (state, queue)
+    }
+
+    /// Records attendance of a played queue in the queue history. Queues are
+    /// identified by queue id and when they finished.
+    ///
+    /// Returns None if the queue isn't in the history.
+    pub fn set_queue_attendance(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        finished_at: DateTime<Utc>,
+        attendance: Attendance,
+    ) -> Option<State> {
+        let mut state = self.clone();
+
+        let record = state
+            .chats
+            .get_mut(chat_id)?
+            .history
+            .iter_mut()
+            .find(|record| record.queue_id == *queue_id && record.finished_at == finished_at)?;
+        record.attendance = Some(attendance);
+
+        Some(state)
+    }
+
+    /// Uses up one of the attendance checks of a played queue, so that the
+    /// check isn't started again while it's in progress. Queues are identified
+    /// by queue id and when they finished.
+    ///
+    /// Returns None if the queue isn't in the history or has no checks left.
+    pub fn claim_attendance_check(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        finished_at: DateTime<Utc>,
+    ) -> Option<State> {
+        let mut state = self.clone();
+
+        let record = state
+            .chats
+            .get_mut(chat_id)?
+            .history
+            .iter_mut()
+            .find(|record| record.queue_id == *queue_id && record.finished_at == finished_at)?;
+        record.attendance_checks_left = record.attendance_checks_left.checked_sub(1)?;
+
+        Some(state)
+    }
+
+    /// Removes a chat queue that timed out without being played, and records
+    /// it in the queue history.
+    ///
+    /// Returns a tuple of new State and the removed queue.
+    pub fn time_out_chat_queue(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+    ) -> (State, Option<Queue>) {
+        let (mut state, queue) = self.rm_chat_queue(chat_id, queue_id);
+
+        if let (Some(chat), Some(queue)) = (state.chats.get_mut(chat_id), &queue) {
+            chat.record_queue(queue_id, queue, false);
+        }
+
+        (state, queue)
+    }
+
+    /// Removes a chat queue that is ready to play, and keeps it in the queue
+    /// history.
+    ///
+    /// Returns a tuple of new State and the removed queue.
+    pub fn fire_chat_queue(&self, chat_id: &ChatId, queue_id: &QueueId) -> (State, Option<Queue>) {
+        let (mut state, queue) = self.rm_chat_queue(chat_id, queue_id);
+
+        if let (Some(chat), Some(queue)) = (state.chats.get_mut(chat_id), &queue) {
+            chat.record_queue(queue_id, queue, true);
+        }
+
+        (state, queue)
+    }
+
+    /// Stores the id of the latest status message of a chat queue.
+    pub fn set_queue_status_msg(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        msg_id: MessageId,
+    ) -> State {
+        let mut state = self.clone();
+
+        let queue = state
+            .chats
+            .get_mut(chat_id)
+            .and_then(|chat| chat.queues.get_mut(queue_id));
+
+        if let Some(queue) = queue {
+            queue.status_msg_id = Some(msg_id);
+        }
+
+        state
+    }
+
+    /// Records that player was added to a chat queue by someone else.
+    pub fn set_player_added_by(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        player: &Player,
+        user_id: UserId,
+    ) -> State {
+        let mut state = self.clone();
+
+        let queue = state
+            .chats
+            .get_mut(chat_id)
+            .and_then(|chat| chat.queues.get_mut(queue_id));
+
+        if let Some(queue) = queue {
+            queue.added_by.retain(|(added, _)| added != player);
+            queue.added_by.push((player.clone(), user_id));
+        }
+
+        state
+    }
+
+    /// Adds a subscription to given chat, replacing the player's previous
+    /// subscription.
+    pub fn subscribe(&self, chat_id: &ChatId, subscription: Subscription) -> State {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        chat.subscriptions
+            .retain(|existing| existing.player != subscription.player);
+        chat.subscriptions.push(subscription);
+
+        state
+    }
+
+    /// Adds an availability rule for player in given chat.
+    ///
+    /// Returns a tuple of new State and the added rule.
+    pub fn add_auto_add_rule(
+        &self,
+        chat_id: &ChatId,
+        player: Player,
+        weekdays: Vec<Weekday>,
+        from: NaiveTime,
+        to: NaiveTime,
+    ) -> (State, AutoAddRule) {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        let id = chat
+            .auto_add_rules
+            .iter()
+            .map(|rule| rule.id)
+            .max()
+            .unwrap_or_default()
+            + 1;
+
+        let rule = AutoAddRule {
+            id,
+            player,
+            weekdays,
+            from,
+            to,
+        };
+        chat.auto_add_rules.push(rule.clone());
+
+        (state, rule)
+    }
+
+    /// Deletes one of player's availability rules. Queues the player was
+    /// already added to are kept.
+    ///
+    /// Returns None if the player has no such rule.
+    pub fn rm_auto_add_rule(&self, chat_id: &ChatId, player: &Player, id: u32) -> Option<State> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let index = chat
+            .auto_add_rules
+            .iter()
+            .position(|rule| rule.id == id && rule.player == *player)?;
+        chat.auto_add_rules.remove(index);
+
+        Some(state)
+    }
+
+    /// Removes player's subscription from given chat.
+    ///
+    /// Returns None if the player has no subscription.
+    pub fn unsubscribe(&self, chat_id: &ChatId, player: &Player) -> Option<State> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let index = chat
+            .subscriptions
+            .iter()
+            .position(|subscription| subscription.player == *player)?;
+        chat.subscriptions.remove(index);
+
+        Some(state)
+    }
+
+    /// Finds subscriptions that should be alerted about a chat queue starting
+    /// at `start` (local time), and marks them as alerted. Subscribers already
+    /// in the queue aren't alerted.
+    ///
+    /// Returns None if there's no one to alert.
+    pub fn alert_subscribers(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        start: NaiveTime,
+    ) -> Option<(State, Vec<Subscription>)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let queue = chat.queues.get_mut(queue_id)?;
+
+        let subscriptions: Vec<Subscription> = chat
+            .subscriptions
+            .iter()
+            .filter(|subscription| {
+                queue.num_players() >= subscription.min_players
+                    && subscription.in_window(start)
+                    && queue.player_tier(&subscription.player).is_none()
+                    && !queue.alerted.contains(&subscription.player)
+            })
+            .cloned()
+            .collect();
+
+        if subscriptions.is_empty() {
+            return None;
+        }
+
+        queue.alerted.extend(
+            subscriptions
+                .iter()
+                .map(|subscription| subscription.player.clone()),
+        );
+
+        Some((state, subscriptions))
+    }
+
+    /// Counts a reroll of teams for a chat queue, or resets the count when
+    /// teams are balanced anew.
+    ///
+    /// Returns a tuple of new State and the number of rerolls, or None if no
+    /// such queue exists.
+    pub fn reroll_teams(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        reroll: bool,
+    ) -> Option<(State, usize)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let queue = chat.queues.get_mut(queue_id)?;
+        queue.teams_rerolls = if reroll { queue.teams_rerolls + 1 } else { 0 };
+        let rerolls = queue.teams_rerolls;
+
+        Some((state, rerolls))
+    }
+
+    /// Removes all queues of given chat.
+    ///
+    /// Returns a tuple of new State and removed queues.
+    pub fn clear_chat_queues(&self, chat_id: &ChatId) -> (State, HashMap<QueueId, Queue>) {
+        let mut state = self.clone();
+
+        let queues = state
+            .chats
+            .get_mut(chat_id)
+            .map(|chat| std::mem::take(&mut chat.queues))
+            .unwrap_or_default();
+
+        (state, queues)
+    }
+
+    /// Marks reminders of a chat queue as sent.
+    pub fn set_queue_reminders_sent(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        reminders: &[i64],
+    ) -> State {
+        let mut state = self.clone();
+
+        let queue = state
+            .chats
+            .get_mut(chat_id)
+            .and_then(|chat| chat.queues.get_mut(queue_id));
+
+        if let Some(queue) = queue {
+            queue.sent_reminders.extend(reminders);
+        }
+
+        state
+    }
+
+    /// Sets the default size of new queues in given chat.
+    pub fn set_chat_queue_size(&self, chat_id: &ChatId, size: Option<usize>) -> State {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        chat.queue_size = size;
+
+        state
+    }
+
+    /// Adds a recurring queue definition to given chat.
+    ///
+    /// Returns a tuple of new State and the added definition.
+    pub fn add_recurring_queue(
+        &self,
+        chat_id: &ChatId,
+        weekdays: Vec<Weekday>,
+        time: NaiveTime,
+        players: Vec<Player>,
+        size: Option<usize>,
+    ) -> (State, RecurringQueue) {
+        let mut state = self.clone();
+
+        let chat = state.chats.entry(*chat_id).or_default();
+        let id = chat
+            .recurring_queues
+            .iter()
+            .map(|recurring| recurring.id)
+            .max()
+            .unwrap_or_default()
+            + 1;
+
+        let recurring = RecurringQueue {
+            id,
+            weekdays,
+            time,
+            players,
+            size,
+            paused: false,
+            last_opened: None,
+        };
+        chat.recurring_queues.push(recurring.clone());
+
+        (state, recurring)
+    }
+
+    /// Pauses or resumes a recurring queue definition.
+    ///
+    /// Returns None if no such definition exists.
+    pub fn set_recurring_queue_paused(
+        &self,
+        chat_id: &ChatId,
+        id: u32,
+        paused: bool,
+    ) -> Option<State> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let recurring = chat.recurring_queues.iter_mut().find(|r| r.id == id)?;
+        recurring.paused = paused;
+
+        Some(state)
+    }
+
+    /// Deletes a recurring queue definition. Already opened queues are kept.
+    ///
+    /// Returns None if no such definition exists.
+    pub fn rm_recurring_queue(&self, chat_id: &ChatId, id: u32) -> Option<State> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let index = chat.recurring_queues.iter().position(|r| r.id == id)?;
+        chat.recurring_queues.remove(index);
+
+        Some(state)
+    }
+
+    /// Opens a queue for an occurrence of a recurring queue definition, adding
+    /// the definition's players to it.
+    ///
+    /// Returns None if no such definition exists or if the occurrence was
+    /// already opened.
+    pub fn open_recurring_queue(
+        &self,
+        chat_id: &ChatId,
+        id: u32,
+        queue_id: &QueueId,
+        add_cmd: String,
+        timeout: DateTime<Utc>,
+    ) -> Option<(State, Queue)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let chat_queue_size = chat.queue_size();
+        let recurring = chat.recurring_queues.iter_mut().find(|r| r.id == id)?;
+
+        if recurring.last_opened >= Some(timeout) {
+            return None;
+        }
+        recurring.last_opened = Some(timeout);
+
+        let size = recurring.size.unwrap_or(chat_queue_size);
+        let players = recurring.players.clone();
+        let auto_players = chat.auto_add_players(queue_id);
+
+        // Merge into an existing queue if someone already added to it.
+        let queue = chat.queues.entry(queue_id.clone()).or_insert_with(|| {
+            let mut queue = Queue::new(timeout, add_cmd, size);
+            for player in auto_players {
+                queue.insert_auto_player(player);
+            }
+            queue
+        });
+
+        for player in players {
+            queue.insert_player(player);
+        }
+
+        let queue = queue.clone();
+
+        Some((state, queue))
+    }
+
+    /// Moves a chat queue and its players to another time. If a queue already
+    /// exists at that time, the players are merged into it.
+    ///
+    /// Returns a tuple of new State, the queue as it was before moving, the
+    /// queue at its new time and whether it was merged into an existing queue,
+    /// or None if no such queue exists.
+    pub fn move_queue(
+        &self,
+        chat_id: &ChatId,
+        from: &QueueId,
+        to: &QueueId,
+        add_cmd: String,
+        timeout: DateTime<Utc>,
+    ) -> Option<(State, Queue, Queue, bool)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let source = chat.queues.remove(from)?;
+        let merged = chat.queues.contains_key(to);
+
+        let queue = match chat.queues.remove(to) {
+            Some(mut target) => {
+                target.merge(&source);
+                target
+            }
+            None => {
+                let mut queue = source.clone();
+                queue.timeout = timeout;
+                queue.add_cmd = add_cmd;
+                queue.status_msg_id = None;
+                queue.sent_reminders = vec![];
+                queue.ready_check = None;
+                queue
+            }
+        };
+        chat.queues.insert(to.clone(), queue.clone());
+
+        Some((state, source, queue, merged))
+    }
+
+    /// Postpones the timeout of a chat queue.
+    ///
+    /// Returns None if no such queue exists.
+    pub fn extend_queue(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        duration: Duration,
+    ) -> Option<(State, Queue)> {
+        let mut state = self.clone();
+
+        let queue = state.chats.get_mut(chat_id)?.queues.get_mut(queue_id)?;
+        queue.timeout += duration;
+        let queue = queue.clone();
+
+        Some((state, queue))
+    }
+
+    /// Starts a ready check on a full chat queue.
+    ///
+    /// Returns None if no such queue exists or if it's not full.
+    pub fn start_ready_check(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        deadline: DateTime<Utc>,
+    ) -> Option<(State, Queue)> {
+        let mut state = self.clone();
+
+        let queue = state.chats.get_mut(chat_id)?.queues.get_mut(queue_id)?;
+
+        if !queue.is_full() {
+            return None;
+        }
+
+        queue.ready_check = Some(ReadyCheck {
+            deadline,
+            confirmed: vec![],
+        });
+        let queue = queue.clone();
+
+        Some((state, queue))
+    }
+
+    /// Confirms that a player in the lineup of a chat queue is ready.
+    ///
+    /// Returns None if the queue has no ongoing ready check, or if the player
+    /// isn't waiting to confirm it.
+    pub fn confirm_ready(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        player: &Player,
+    ) -> Option<(State, Queue)> {
+        let mut state = self.clone();
+
+        let queue = state.chats.get_mut(chat_id)?.queues.get_mut(queue_id)?;
+
+        if !queue.unconfirmed_players().contains(player) {
+            return None;
+        }
+
+        queue.ready_check.as_mut()?.confirmed.push(player.clone());
+        let queue = queue.clone();
+
+        Some((state, queue))
+    }
+
+    /// Ends the current round of a ready check by dropping players in the
+    /// lineup who didn't confirm. If reserve players take their place, the
+    /// ready check continues until `deadline`, otherwise it's cancelled and
+    /// the queue stays open at least until `deadline` for others to join.
+    ///
+    /// Returns a tuple of new State, the queue and dropped players, or None if
+    /// the queue has no ongoing ready check.
+    pub fn expire_ready_check(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        deadline: DateTime<Utc>,
+    ) -> Option<(State, Queue, Vec<Player>)> {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id)?;
+        let queue = chat.queues.get_mut(queue_id)?;
+        queue.ready_check.as_ref()?;
+
+        let dropped = queue.unconfirmed_players();
+        for player in &dropped {
+            queue.remove_player(player);
+        }
+
+        match &mut queue.ready_check {
+            Some(ready_check) => ready_check.deadline = deadline,
+            // A timed queue's timeout has already passed by now, so it would
+            // otherwise time out right away.
+            None => queue.timeout = queue.timeout.max(deadline),
+        }
+
+        let queue = queue.clone();
+
+        // Remove queue if nobody is left in it. Only queues that time out or
+        // fire are kept in the queue history.
+        if !queue.has_players() {
+            chat.queues.remove(queue_id);
+        }
+
+        Some((state, queue, dropped))
+    }
+
+    /// Adds/removes player from given chat queue.
+    ///
+    /// If `size` is given, the queue is resized to it. New queues otherwise
+    /// default to the chat's queue size. Players already in the queue who
+    /// toggle with a size stay in the queue, so that they can resize it.
+    ///
+    /// Removes the queue once it's empty.
+    ///
+    /// Returns a tuple of new State, the result, the operation and players who
+    /// were promoted from the reserve to the lineup.
+    pub fn add_remove_player(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        args: AddRemovePlayerArgs,
+    ) -> (State, AddRemovePlayerResult, AddRemovePlayerOp, Vec<Player>) {
+        let AddRemovePlayerArgs {
+            add_cmd,
+            timeout,
+            size,
+            player,
+            mode,
+        } = args;
+        let mut state = self.clone();
+
+        // Ensure both chat and queue exists in respective HashMaps.
+        let chat = state.chats.entry(*chat_id).or_default();
+        let chat_queue_size = chat.queue_size();
+        let auto_players = chat.auto_add_players(queue_id);
+
+        // New queues pick up players whose availability rules match, except
+        // for the player themselves, whose own command decides.
+        let queue = chat.queues.entry(queue_id.clone()).or_insert_with(|| {
+            let mut queue = Queue::new(timeout, add_cmd, chat_queue_size);
+            for auto_player in auto_players {
+                if auto_player != player {
+                    queue.insert_auto_player(auto_player);
+                }
+            }
+            queue
+        });
+
+        let (lineup, _) = queue.get_players();
+
+        if let Some(size) = size {
+            queue.set_size(size);
+        }
+
+        let tier = queue.player_tier(&player);
+
+        let op = match (mode, tier) {
+            // Resizing a queue the player is already in keeps them in it.
+            (JoinMode::Toggle, Some(_)) if size.is_some() => {
+                AddRemovePlayerOp::QueueResized(queue.size())
+            }
+            // Toggling or repeating the same mode removes the player.
+            (JoinMode::Toggle, Some(JoinMode::Yes | JoinMode::Reserve))
+            | (JoinMode::Reserve, Some(JoinMode::Reserve))
+            | (JoinMode::Maybe, Some(JoinMode::Maybe)) => {
+                queue.leave(&player);
+                AddRemovePlayerOp::PlayerRemoved(player.clone())
+            }
+            (JoinMode::Yes, Some(JoinMode::Yes)) => AddRemovePlayerOp::PlayerAdded(player.clone()),
+            (JoinMode::Toggle | JoinMode::Yes, _) => {
+                queue.remove_player(&player);
+                queue.insert_player(player.clone());
+                AddRemovePlayerOp::PlayerAdded(player.clone())
+            }
+            (JoinMode::Reserve, _) => {
+                queue.remove_player(&player);
+                queue.insert_reserve_player(player.clone());
+                AddRemovePlayerOp::PlayerAdded(player.clone())
+            }
+            (JoinMode::Maybe, _) => {
+                queue.remove_player(&player);
+                queue.insert_maybe_player(player.clone());
+                AddRemovePlayerOp::MaybePlayerAdded(player.clone())
+            }
+        };
+
+        // Players joining a lineup with free spots aren't promoted.
+        let mut promoted = promoted_players(&lineup, queue);
+        promoted.retain(|promoted| *promoted != player);
+
+        let result = if !queue.has_players() {
+            // Remove queue if it's empty after remove operation.
+            let queue = chat.queues.remove(queue_id).unwrap();
+            AddRemovePlayerResult::QueueEmpty(queue)
+        } else if queue.is_full() {
+            AddRemovePlayerResult::QueueFull(queue.clone())
+        } else {
+            AddRemovePlayerResult::PlayerQueued(queue.clone())
+        };
+
+        (state, result, op, promoted)
+    }
+
+    /// Removes player from all chat queues.
+    ///
+    /// Returns a tuple of new State and affected queues, along with players
+    /// who were promoted from the reserve to the lineup of each queue.
+    pub fn rm_player(
+        &self,
+        chat_id: &ChatId,
+        player: &Player,
+    ) -> (State, HashMap<QueueId, (Queue, Vec<Player>)>) {
+        self.rm_player_where(chat_id, player, |_, _| true)
+    }
+
+    /// Removes player from chat queues matching `filter`.
+    ///
+    /// Returns a tuple of new State and affected queues, along with players
+    /// who were promoted from the reserve to the lineup of each queue.
+    pub fn rm_player_where(
+        &self,
+        chat_id: &ChatId,
+        player: &Player,
+        filter: impl Fn(&QueueId, &Queue) -> bool,
+    ) -> (State, HashMap<QueueId, (Queue, Vec<Player>)>) {
+        let mut state = self.clone();
+
+        let chat = state.chats.get_mut(chat_id);
+
+        // Maintain a list of queues affected by remove operation.
+        let mut affected_queues = HashMap::new();
+
+        if let Some(chat) = chat {
+            // Remove player from matching chat queues
+            for (queue_id, queue) in chat.queues.iter_mut() {
+                if !filter(queue_id, queue) {
+                    continue;
+                }
+
+                let (lineup, _) = queue.get_players();
+                let removed = queue.leave(player);
+
+                if removed {
+                    let promoted = promoted_players(&lineup, queue);
+                    affected_queues.insert(queue_id.clone(), (queue.clone(), promoted));
+                }
+            }
+
+            // Filter out queues that became empty. Queues that were opened
+            // without players (e.g. recurring queues) are kept.
+            chat.queues.retain(|queue_id, queue| {
+                queue.has_players() || !affected_queues.contains_key(queue_id)
+            });
+        }
+
+        (state, affected_queues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util::{add, players, queue_with, CHAT_ID};
+
+    #[test]
+    fn deserializes_legacy_username_players() {
+        let json = r#"{"players":["alice",{"id":1,"name":"bob"}],"timeout":"2024-12-24T17:30:00Z","add_cmd":"/1930"}"#;
+        let queue: Queue = serde_json::from_str(json).unwrap();
+
+        let (players, _) = queue.get_players();
+        assert_eq!(players[0].id, None);
+        assert_eq!(players[0].name, Username::new(String::from("alice")));
+        assert_eq!(players[1].id, Some(UserId(1)));
+        assert_eq!(queue.created_at, DateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn identifies_players_added_by_name() {
+        let players = players(2);
+        let by_name = Player::new(None, players[0].name.clone());
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues.insert(
+            QueueId::instant(),
+            queue_with(&[by_name, players[1].clone()]),
+        );
+
+        let state = state.identify_player(&players[0]).unwrap();
+        let (lineup, _) = state.chats[&CHAT_ID].queues[&QueueId::instant()].get_players();
+        assert_eq!(lineup, players);
+        assert_eq!(lineup[0].id, players[0].id);
+
+        // Renaming keeps the player in place.
+        let renamed = Player::new(players[0].id, Username::new(String::from("p1 renamed")));
+        let state = state.identify_player(&renamed).unwrap();
+        let (lineup, _) = state.chats[&CHAT_ID].queues[&QueueId::instant()].get_players();
+        assert_eq!(lineup[0].name, renamed.name);
+
+        assert!(state.identify_player(&renamed).is_none());
+    }
+
+    #[test]
+    fn resolves_players_by_unique_name() {
+        let name = Username::new(String::from("alice"));
+        let mut state = State::default();
+
+        assert_eq!(state.player_by_name(name.clone()).unwrap().id, None);
+
+        state.users.insert(UserId(1), name.clone());
+        assert_eq!(
+            state.player_by_name(name.clone()).unwrap().id,
+            Some(UserId(1))
+        );
+
+        state.users.insert(UserId(2), name.clone());
+        assert!(state.player_by_name(name).is_none());
+    }
+
+    #[test]
+    fn ready_check_replaces_unconfirmed_players_with_reserves() {
+        let queue_id = QueueId::instant();
+        let players = players(3);
+
+        let mut queue = queue_with(&players);
+        queue.set_size(2);
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues.insert(queue_id.clone(), queue);
+
+        let (state, _) = state
+            .start_ready_check(&CHAT_ID, &queue_id, Utc::now())
+            .unwrap();
+
+        // Reserve players can't confirm.
+        assert!(state
+            .confirm_ready(&CHAT_ID, &queue_id, &players[2])
+            .is_none());
+
+        let (state, queue) = state
+            .confirm_ready(&CHAT_ID, &queue_id, &players[0])
+            .unwrap();
+        assert!(!queue.is_ready());
+
+        let (state, queue, dropped) = state
+            .expire_ready_check(&CHAT_ID, &queue_id, Utc::now())
+            .unwrap();
+        assert_eq!(dropped, vec![players[1].clone()]);
+        assert_eq!(queue.unconfirmed_players(), vec![players[2].clone()]);
+
+        let (_, queue) = state
+            .confirm_ready(&CHAT_ID, &queue_id, &players[2])
+            .unwrap();
+        assert!(queue.is_ready());
+    }
+
+    #[test]
+    fn failed_ready_check_keeps_queue_open() {
+        let queue_id = QueueId::instant();
+        let players = players(2);
+
+        let mut queue = queue_with(&players);
+        queue.set_size(2);
+        queue.timeout = Utc::now();
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues.insert(queue_id.clone(), queue);
+
+        let (state, _) = state
+            .start_ready_check(&CHAT_ID, &queue_id, Utc::now())
+            .unwrap();
+        let (state, _) = state
+            .confirm_ready(&CHAT_ID, &queue_id, &players[0])
+            .unwrap();
+
+        let deadline = Utc::now() + Duration::minutes(5);
+        let (_, queue, dropped) = state
+            .expire_ready_check(&CHAT_ID, &queue_id, deadline)
+            .unwrap();
+        assert_eq!(dropped, vec![players[1].clone()]);
+        assert!(queue.ready_check.is_none());
+        assert_eq!(queue.timeout, deadline);
+    }
+
+    #[test]
+    fn reports_players_promoted_from_reserve() {
+        let queue_id = QueueId::instant();
+        let players = players(3);
+
+        // Reserve-only players stay in the reserve while there's room.
+        let (state, ..) = add(
+            &State::default(),
+            &queue_id,
+            Some(2),
+            &players[0],
+            JoinMode::Toggle,
+        );
+        let (state, ..) = add(&state, &queue_id, Some(2), &players[2], JoinMode::Reserve);
+        let queue = &state.chats[&CHAT_ID].queues[&queue_id];
+        assert_eq!(queue.num_players(), 1);
+        assert_eq!(queue.get_players().1, Some(vec![players[2].clone()]));
+
+        let (state, _, _, promoted) =
+            add(&state, &queue_id, Some(2), &players[1], JoinMode::Toggle);
+        assert!(promoted.is_empty());
+
+        // Someone leaving the full lineup promotes the reserve-only player.
+        let (state, promoted) = state.rm_player(&CHAT_ID, &players[0]);
+        let (queue, rm_promoted) = &promoted[&queue_id];
+        assert_eq!(rm_promoted, &vec![players[2].clone()]);
+        assert!(queue.is_full());
+
+        let (_, _, _, promoted) = add(&state, &queue_id, Some(2), &players[1], JoinMode::Toggle);
+        assert!(promoted.is_empty());
+    }
+
+    #[test]
+    fn records_only_lineup_players_leaving_full_queues() {
+        let queue_id = QueueId::instant();
+        let players = players(3);
+
+        let (mut state, ..) = add(
+            &State::default(),
+            &queue_id,
+            Some(2),
+            &players[0],
+            JoinMode::Toggle,
+        );
+        for player in &players[1..] {
+            (state, ..) = add(&state, &queue_id, None, player, JoinMode::Toggle);
+        }
+
+        // Switching to the reserve isn't leaving, even after being promoted
+        // back to the lineup.
+        let (state, ..) = add(&state, &queue_id, None, &players[1], JoinMode::Reserve);
+        let (state, ..) = add(&state, &queue_id, None, &players[2], JoinMode::Toggle);
+        let (state, ..) = add(&state, &queue_id, None, &players[1], JoinMode::Reserve);
+
+        let queue = &state.chats[&CHAT_ID].queues[&queue_id];
+        assert_eq!(queue.left_after_full, vec![players[2].clone()]);
+    }
+
+    #[test]
+    fn emptied_queues_are_not_recorded() {
+        let queue_id = QueueId::instant();
+        let players = players(2);
+
+        let (state, ..) = add(
+            &State::default(),
+            &queue_id,
+            None,
+            &players[0],
+            JoinMode::Toggle,
+        );
+        let (state, result, ..) = add(&state, &queue_id, None, &players[0], JoinMode::Toggle);
+        assert!(matches!(result, AddRemovePlayerResult::QueueEmpty(_)));
+        assert!(state.chats[&CHAT_ID].history.is_empty());
+
+        let mut queue = queue_with(&players);
+        queue.set_size(2);
+        let mut state = state;
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues.insert(queue_id.clone(), queue);
+
+        let (state, _) = state
+            .start_ready_check(&CHAT_ID, &queue_id, Utc::now())
+            .unwrap();
+        let (state, ..) = state
+            .expire_ready_check(&CHAT_ID, &queue_id, Utc::now())
+            .unwrap();
+        let chat = &state.chats[&CHAT_ID];
+        assert!(!chat.queues.contains_key(&queue_id));
+        assert!(chat.history.is_empty());
+    }
+
+    #[test]
+    fn resizing_keeps_queued_player() {
+        let queue_id = QueueId::instant();
+        let players = players(1);
+
+        let (state, ..) = add(
+            &State::default(),
+            &queue_id,
+            None,
+            &players[0],
+            JoinMode::Toggle,
+        );
+        let (state, _, op, _) = add(&state, &queue_id, Some(2), &players[0], JoinMode::Toggle);
+        assert!(matches!(op, AddRemovePlayerOp::QueueResized(2)));
+
+        let queue = &state.chats[&CHAT_ID].queues[&queue_id];
+        assert_eq!(queue.size(), 2);
+        assert_eq!(queue.get_players().0, players);
+
+        let (_, _, op, _) = add(&state, &queue_id, None, &players[0], JoinMode::Toggle);
+        assert!(matches!(op, AddRemovePlayerOp::PlayerRemoved(_)));
+    }
+
+    #[test]
+    fn maybe_players_do_not_fill_queue() {
+        let queue_id = QueueId::instant();
+        let players = players(2);
+        let (alice, bob) = (&players[0], &players[1]);
+
+        let (state, ..) = add(
+            &State::default(),
+            &queue_id,
+            Some(2),
+            alice,
+            JoinMode::Toggle,
+        );
+        let (state, result, ..) = add(&state, &queue_id, Some(2), bob, JoinMode::Maybe);
+        let AddRemovePlayerResult::PlayerQueued(queue) = result else {
+            panic!("maybe player filled the queue");
+        };
+        assert_eq!(queue.get_maybe_players(), vec![bob.clone()]);
+
+        let (_, result, ..) = add(&state, &queue_id, Some(2), bob, JoinMode::Yes);
+        let AddRemovePlayerResult::QueueFull(queue) = result else {
+            panic!("queue didn't fill");
+        };
+        assert!(queue.get_maybe_players().is_empty());
+
+        // Only maybe players left keeps the queue around.
+        let (state, ..) = add(&state, &queue_id, Some(2), alice, JoinMode::Toggle);
+        assert!(state.chats[&CHAT_ID].queues.contains_key(&queue_id));
+    }
+
+    #[test]
+    fn moving_queue_merges_players_in_order() {
+        let (from, to) = (
+            QueueId::new(String::from("a")),
+            QueueId::new(String::from("b")),
+        );
+        let players = players(3);
+
+        let mut source = queue_with(&[players[2].clone(), players[0].clone()]);
+        source.insert_maybe_player(players[1].clone());
+        let target = queue_with(&[players[0].clone(), players[1].clone()]);
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues.insert(from.clone(), source);
+        chat.queues.insert(to.clone(), target);
+
+        let (state, _, queue, merged) = state
+            .move_queue(&CHAT_ID, &from, &to, String::from("/b"), Utc::now())
+            .unwrap();
+
+        assert!(merged);
+        assert!(!state.chats[&CHAT_ID].queues.contains_key(&from));
+        let (lineup, _) = queue.get_players();
+        assert_eq!(lineup, players);
+        assert!(queue.get_maybe_players().is_empty());
+    }
+
+    #[test]
+    fn merging_queues_keeps_player_metadata() {
+        let players = players(4);
+        let deadline = Utc::now();
+
+        let mut source = queue_with(&[players[0].clone()]);
+        source.insert_auto_player(players[1].clone());
+        source.added_by.push((players[0].clone(), UserId(9)));
+        source.left_after_full.push(players[3].clone());
+        source.ready_check = Some(ReadyCheck {
+            deadline,
+            confirmed: vec![players[0].clone()],
+        });
+
+        let mut target = queue_with(&[players[2].clone()]);
+        target.ready_check = Some(ReadyCheck {
+            deadline,
+            confirmed: vec![players[2].clone()],
+        });
+
+        target.merge(&source);
+
+        assert_eq!(target.added_by(&players[0]), Some(UserId(9)));
+        assert!(target.is_auto_added(&players[1]));
+        assert_eq!(target.left_after_full, vec![players[3].clone()]);
+        assert_eq!(
+            target.ready_check.unwrap().confirmed,
+            vec![players[2].clone(), players[0].clone()]
+        );
+    }
+
+    #[test]
+    fn alerts_subscribers_once_within_window() {
+        let queue_id = QueueId::instant();
+        let players = players(2);
+        let time = |s| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.queues
+            .insert(queue_id.clone(), queue_with(&players[..1]));
+
+        let state = state.subscribe(
+            &CHAT_ID,
+            Subscription {
+                player: players[1].clone(),
+                min_players: 1,
+                window: Some((time("22:00"), time("02:00"))),
+                dm: false,
+            },
+        );
+
+        assert!(state
+            .alert_subscribers(&CHAT_ID, &queue_id, time("19:30"))
+            .is_none());
+
+        let (state, subscriptions) = state
+            .alert_subscribers(&CHAT_ID, &queue_id, time("23:00"))
+            .unwrap();
+        assert_eq!(subscriptions.len(), 1);
+
+        assert!(state
+            .alert_subscribers(&CHAT_ID, &queue_id, time("23:00"))
+            .is_none());
+    }
+
+    #[test]
+    fn new_timed_queues_pick_up_auto_add_players() {
+        let players = players(2);
+        let (alice, bob) = (&players[0], &players[1]);
+        let time = |s| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+
+        let (state, _) = State::default().add_auto_add_rule(
+            &CHAT_ID,
+            bob.clone(),
+            vec![Weekday::Tue],
+            time("19:00"),
+            time("21:00"),
+        );
+
+        let queued = |queue_id: &str| {
+            let queue_id = QueueId::new(String::from(queue_id));
+            let (_, result, ..) = add(&state, &queue_id, None, alice, JoinMode::Toggle);
+            let AddRemovePlayerResult::PlayerQueued(queue) = result else {
+                panic!("unexpected add result");
+            };
+            queue
+        };
+
+        // 2024-12-24 is a Tuesday.
+        let queue = queued("2024-12-24 19:30");
+        assert_eq!(queue.get_players().0, vec![bob.clone(), alice.clone()]);
+        assert!(queue.is_auto_added(bob));
+
+        let queue = queued("2024-12-24 22:00");
+        assert_eq!(queue.get_players().0, vec![alice.clone()]);
+    }
+}
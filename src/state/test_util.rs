@@ -0,0 +1,50 @@
+//! Helpers shared by tests that build up a State.
+
+use chrono::Utc;
+use teloxide::types::{ChatId, UserId};
+
+use super::{
+    AddRemovePlayerArgs, AddRemovePlayerOp, AddRemovePlayerResult, JoinMode, Queue, State,
+    DEFAULT_QUEUE_SIZE,
+};
+use crate::types::{Player, QueueId, Username};
+
+/// Chat that test queues are created in.
+pub const CHAT_ID: ChatId = ChatId(1);
+
+/// Returns players p1..=pn with user ids matching their numbers.
+pub fn players(n: u64) -> Vec<Player> {
+    (1..=n)
+        .map(|id| Player::new(Some(UserId(id)), Username::new(format!("p{}", id))))
+        .collect()
+}
+
+/// Returns a queue of default size with given players in its lineup.
+pub fn queue_with(players: &[Player]) -> Queue {
+    let mut queue = Queue::new(Utc::now(), String::from("/add"), DEFAULT_QUEUE_SIZE);
+    for player in players {
+        queue.insert_player(player.clone());
+    }
+    queue
+}
+
+/// Adds/removes a player in a queue of `CHAT_ID`, like `/add` does.
+pub fn add(
+    state: &State,
+    queue_id: &QueueId,
+    size: Option<usize>,
+    player: &Player,
+    mode: JoinMode,
+) -> (State, AddRemovePlayerResult, AddRemovePlayerOp, Vec<Player>) {
+    state.add_remove_player(
+        &CHAT_ID,
+        queue_id,
+        AddRemovePlayerArgs {
+            add_cmd: String::from("/add"),
+            timeout: Utc::now(),
+            size,
+            player: player.clone(),
+            mode,
+        },
+    )
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId};
+
+use super::{Chat, State};
+use crate::types::{Player, QueueId};
+
+/// Step of a map veto.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum VetoStep {
+    Ban,
+    Pick,
+}
+
+/// Map veto, where two sides take turns banning and picking maps until one
+/// map remains.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub struct Veto {
+    pub maps: Vec<String>,
+    pub best_of: usize,
+    pub steps: Vec<VetoStep>,
+
+    /// Maps banned or picked so far, as indices into `maps`, one per step.
+    pub actions: Vec<usize>,
+
+    /// Players acting for each side. Anyone can act if not given.
+    pub sides: Option<[Player; 2]>,
+
+    /// Telegram message showing the veto.
+    pub msg_id: Option<MessageId>,
+}
+
+impl Veto {
+    /// Creates a best of one or best of three veto. In a best of three, both
+    /// sides pick a map after up to two bans, and the last remaining map is
+    /// the decider.
+    ///
+    /// Returns None if there aren't enough maps.
+    pub fn new(maps: Vec<String>, best_of: usize, sides: Option<[Player; 2]>) -> Option<Veto> {
+        let n = maps.len();
+
+        let steps = match best_of {
+            1 if n >= 2 => vec![VetoStep::Ban; n - 1],
+            3 if n >= 3 => {
+                let bans = (n - 3).min(2);
+                let mut steps = vec![VetoStep::Ban; bans];
+                steps.extend([VetoStep::Pick, VetoStep::Pick]);
+                steps.extend(vec![VetoStep::Ban; n - 3 - bans]);
+                steps
+            }
+            _ => return None,
+        };
+
+        Some(Veto {
+            maps,
+            best_of,
+            steps,
+            actions: vec![],
+            sides,
+            msg_id: None,
+        })
+    }
+
+    /// Returns indices of maps that haven't been banned or picked.
+    pub fn remaining(&self) -> Vec<usize> {
+        (0..self.maps.len())
+            .filter(|i| !self.actions.contains(i))
+            .collect()
+    }
+
+    /// Returns the side whose turn it is and what they do, or None if the
+    /// veto is finished. Sides alternate, starting with the first side.
+    pub fn next_step(&self) -> Option<(usize, VetoStep)> {
+        let step = self.actions.len();
+        self.steps.get(step).map(|action| (step % 2, *action))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_step().is_none()
+    }
+
+    /// Returns the maps to be played in order: picked maps followed by the
+    /// last remaining map.
+    pub fn played_maps(&self) -> Vec<&str> {
+        let mut maps: Vec<&str> = self
+            .actions
+            .iter()
+            .zip(&self.steps)
+            .filter(|(_, step)| **step == VetoStep::Pick)
+            .map(|(i, _)| self.maps[*i].as_str())
+            .collect();
+
+        if let [decider] = self.remaining()[..] {
+            maps.push(&self.maps[decider]);
+        }
+
+        maps
+    }
+}
+
+impl Chat {
+    /// Returns the veto of the most recently played queue with given id.
+    fn played_veto_mut(&mut self, queue_id: &QueueId) -> Option<&mut Veto> {
+        self.history
+            .iter_mut()
+            .rev()
+            .find(|record| record.filled && record.queue_id == *queue_id)?
+            .veto
+            .as_mut()
+    }
+}
+
+impl State {
+    /// Starts a map veto for the chat's most recently played queue, replacing
+    /// any earlier veto of it.
+    ///
+    /// Returns a tuple of new State and the id of the queue, or None if no
+    /// queue has been played.
+    pub fn start_veto(&self, chat_id: &ChatId, veto: Veto) -> Option<(State, QueueId)> {
+        let mut state = self.clone();
+
+        let record = state
+            .chats
+            .get_mut(chat_id)?
+            .history
+            .iter_mut()
+            .rev()
+            .find(|record| record.filled)?;
+        record.veto = Some(veto);
+        let queue_id = record.queue_id.clone();
+
+        Some((state, queue_id))
+    }
+
+    /// Sets the Telegram message showing the veto of a played queue.
+    pub fn set_veto_msg(&self, chat_id: &ChatId, queue_id: &QueueId, msg_id: MessageId) -> State {
+        let mut state = self.clone();
+
+        if let Some(veto) = state
+            .chats
+            .get_mut(chat_id)
+            .and_then(|chat| chat.played_veto_mut(queue_id))
+        {
+            veto.msg_id = Some(msg_id);
+        }
+
+        state
+    }
+
+    /// Bans or picks a map in the veto of a played queue on behalf of
+    /// `player`.
+    ///
+    /// Returns a tuple of new State and the veto, or None if there's no such
+    /// veto, it isn't the player's turn or the map is no longer available.
+    pub fn veto_map(
+        &self,
+        chat_id: &ChatId,
+        queue_id: &QueueId,
+        player: &Player,
+        map: usize,
+    ) -> Option<(State, Veto)> {
+        let mut state = self.clone();
+
+        let veto = state.chats.get_mut(chat_id)?.played_veto_mut(queue_id)?;
+        let (side, _) = veto.next_step()?;
+
+        let allowed = veto
+            .sides
+            .as_ref()
+            .is_none_or(|sides| sides[side] == *player);
+        if !allowed || !veto.remaining().contains(&map) {
+            return None;
+        }
+        veto.actions.push(map);
+
+        let veto = veto.clone();
+
+        Some((state, veto))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{
+        test_util::{players, queue_with, CHAT_ID},
+        QueueRecord,
+    };
+
+    #[test]
+    fn vetoes_best_of_three_maps() {
+        let maps: Vec<String> = (0..7).map(|i| format!("de_map{i}")).collect();
+        let mut veto = Veto::new(maps, 3, None).unwrap();
+
+        use VetoStep::{Ban, Pick};
+        assert_eq!(veto.steps, vec![Ban, Ban, Pick, Pick, Ban, Ban]);
+
+        for (step, map) in [6, 5, 0, 1, 4, 3].into_iter().enumerate() {
+            assert_eq!(veto.next_step().map(|(side, _)| side), Some(step % 2));
+            veto.actions.push(map);
+        }
+
+        assert!(veto.is_finished());
+        assert_eq!(veto.played_maps(), vec!["de_map0", "de_map1", "de_map2"]);
+        assert!(Veto::new(vec![String::from("de_map0")], 1, None).is_none());
+    }
+
+    #[test]
+    fn vetoes_most_recently_played_queue() {
+        let queue_id = QueueId::instant();
+        let maps: Vec<String> = (0..2).map(|i| format!("de_map{i}")).collect();
+        let queue = queue_with(&players(2));
+
+        let mut state = State::default();
+        let chat = state.chats.entry(CHAT_ID).or_default();
+        chat.history.push(QueueRecord::new(&queue_id, &queue, true));
+        chat.history
+            .push(QueueRecord::new(&queue_id, &queue, false));
+
+        let veto = Veto::new(maps, 1, None).unwrap();
+        let (state, _) = state.start_veto(&CHAT_ID, veto).unwrap();
+        let (state, veto) = state
+            .veto_map(&CHAT_ID, &queue_id, &players(1)[0], 0)
+            .unwrap();
+        assert!(veto.is_finished());
+
+        let history = &state.chats[&CHAT_ID].history;
+        assert!(history[0].veto.is_some());
+        assert!(history[1].veto.is_none());
+    }
+}
@@ -1,32 +1,36 @@
-use crate::state::State;
+use crate::{state::State, storage::Storage};
+use chrono_tz::Tz;
+use color_eyre::Result;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, Notify, RwLock};
 
-/// Path of the state file, relative to CWD.
-const STATE_FILE_PATH: &str = "state.json";
-
-/// Handles reading/write state from/to both memory and disk.
-#[derive(Clone, Default)]
+/// Handles reading/write state from/to both memory and storage.
+#[derive(Clone)]
 pub struct StateContainer {
     state: Arc<RwLock<State>>,
+    changed: Arc<Notify>,
+    storage: Arc<dyn Storage>,
+
+    /// Held while saving state to storage.
+    save_lock: Arc<Mutex<()>>,
 }
 
 impl StateContainer {
-    /// Try restoring state from file.
+    /// Restores state from storage.
     ///
-    /// Fails if there was an error while deserializing from JSON, in other
-    /// error cases returns default (empty) state.
-    pub async fn try_read_from_file() -> Result<StateContainer, serde_json::Error> {
-        let file = tokio::fs::read_to_string(STATE_FILE_PATH).await;
-
-        match file {
-            Ok(json) => {
-                let state: State = serde_json::from_str(&json)?;
-                let state = Arc::new(RwLock::new(state));
-                Ok(StateContainer { state })
-            }
-            Err(_) => Ok(Default::default()),
-        }
+    /// Returns default (empty) state if nothing has been stored yet, and fails
+    /// if the stored state can't be read.
+    pub async fn load(storage: Arc<dyn Storage>, tz: &Tz) -> Result<StateContainer> {
+        let tz = *tz;
+        let load_storage = storage.clone();
+        let state = tokio::task::spawn_blocking(move || load_storage.load(&tz)).await??;
+
+        Ok(StateContainer {
+            state: Arc::new(RwLock::new(state)),
+            changed: Default::default(),
+            storage,
+            save_lock: Default::default(),
+        })
     }
 
     /// Returns current state of the RwLock.
@@ -35,18 +39,73 @@ impl StateContainer {
         self.state.read().await.clone()
     }
 
-    /// Writes new state to the RwLock and JSON state file.
-    pub async fn write(&self, state: State) {
-        // Only hold onto RwLock inside this block
-        {
-            let mut unlocked_state = self.state.write().await;
-            *unlocked_state = state.clone();
+    /// Waits until state is written. A write that happens while nobody is
+    /// waiting is remembered, so the next call returns immediately.
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    /// Derives new state from current state with `f`, holding the RwLock
+    /// for the whole mutation so that concurrent updates can't overwrite each
+    /// other. The new state is then saved to storage once.
+    ///
+    /// `f` returns new state along with a value for the caller, or None to
+    /// leave state unchanged, in which case None is returned.
+    pub async fn update<T>(&self, f: impl FnOnce(&State) -> Option<(State, T)>) -> Option<T> {
+        let mut unlocked_state = self.state.write().await;
+        let (state, value) = f(&unlocked_state)?;
+        *unlocked_state = state.clone();
+
+        // Take the save lock before releasing the RwLock, so that states are
+        // saved in the order they were written.
+        let _save_lock = self.save_lock.lock().await;
+        drop(unlocked_state);
+
+        self.changed.notify_one();
+
+        let storage = self.storage.clone();
+        match tokio::task::spawn_blocking(move || storage.save(&state)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => eprintln!("Error while saving state: {}", error),
+            Err(error) => eprintln!("Error while saving state: {}", error),
         }
 
-        let json = serde_json::to_string(&state).unwrap();
-        let file_res = tokio::fs::write(STATE_FILE_PATH, json).await;
-        if let Err(error) = file_res {
-            eprintln!("Error while writing state file: {}", error);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::sqlite::SqliteStorage,
+        types::{Player, Username},
+    };
+    use teloxide::types::UserId;
+
+    #[tokio::test]
+    async fn concurrent_updates_are_not_lost() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open(":memory:").unwrap());
+        let sc = StateContainer::load(storage.clone(), &tz).await.unwrap();
+
+        let updates: Vec<_> = (0..20)
+            .map(|n| {
+                let sc = sc.clone();
+                tokio::spawn(async move {
+                    let player =
+                        Player::new(Some(UserId(n)), Username::new(format!("player{}", n)));
+                    sc.update(|state| Some((state.identify_player(&player)?, ())))
+                        .await
+                })
+            })
+            .collect();
+
+        for update in updates {
+            update.await.unwrap();
         }
+
+        assert_eq!(sc.read().await.users.len(), 20);
+        assert_eq!(storage.load(&tz).unwrap().users.len(), 20);
     }
 }
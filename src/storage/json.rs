@@ -0,0 +1,219 @@
+use crate::{command::QueueDay, state::State, storage::Storage, types::QueueId};
+use chrono::{NaiveTime, Utc};
+use chrono_tz::Tz;
+use color_eyre::{eyre::eyre, Result};
+use serde_json::{Map, Value};
+use std::{
+    fs::File,
+    io::{ErrorKind, Write},
+    time::{Duration, SystemTime},
+};
+
+/// Number of rotated backups of the state file, e.g. `state.json.1` for the
+/// newest one.
+const STATE_BACKUPS: usize = 3;
+
+/// Minimum time between rotating backups.
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Version of the state file format written by this build. Bump this and add
+/// a migration to `MIGRATIONS` when a change to `State` can't be read from
+/// older state files using serde defaults.
+pub(super) const STATE_VERSION: u64 = 1;
+
+/// Migrations between state file versions. The migration at index `i`
+/// migrates a state file from version `i` to `i + 1`.
+const MIGRATIONS: [fn(&mut Value, &Tz); STATE_VERSION as usize] = [migrate_legacy_timeouts];
+
+/// Stores state as a single JSON file. Writes go to a temporary file which
+/// then replaces the state file, and the previous state file is periodically
+/// kept as a backup.
+pub struct JsonStorage {
+    path: String,
+}
+
+impl JsonStorage {
+    pub fn new(path: &str) -> JsonStorage {
+        JsonStorage {
+            path: path.to_string(),
+        }
+    }
+
+    fn backup_path(&self, n: usize) -> String {
+        format!("{}.{}", self.path, n)
+    }
+
+    /// Restores state from the newest readable backup.
+    fn read_backup(&self, tz: &Tz) -> Result<State> {
+        for n in 1..=STATE_BACKUPS {
+            let path = self.backup_path(n);
+            let Ok(json) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+
+            match parse_state(&json, tz) {
+                Ok(state) => {
+                    eprintln!("Restored state from backup {}", path);
+                    return Ok(state);
+                }
+                Err(error) => eprintln!("Error while reading backup {}: {}", path, error),
+            }
+        }
+
+        Err(eyre!(
+            "State file is corrupt and no backup could be read. Fix or remove {} to start over.",
+            self.path
+        ))
+    }
+
+    /// Moves each backup one step older, and backs up the current state file
+    /// as the newest backup. Does nothing if the newest backup is recent
+    /// enough.
+    fn rotate_backups(&self) -> std::io::Result<()> {
+        let age = std::fs::metadata(self.backup_path(1))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+        if age.is_some_and(|age| age < BACKUP_INTERVAL) {
+            return Ok(());
+        }
+
+        for n in (1..STATE_BACKUPS).rev() {
+            match std::fs::rename(self.backup_path(n), self.backup_path(n + 1)) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+
+        match std::fs::copy(&self.path, self.backup_path(1)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    /// Reads the state file, falling back to the newest readable backup if the
+    /// state file is corrupt.
+    fn load(&self, tz: &Tz) -> Result<State> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(json) => parse_state(&json, tz).or_else(|error| {
+                eprintln!("Error while reading state file: {}", error);
+                self.read_backup(tz)
+            }),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Default::default()),
+            Err(error) => Err(eyre!("Error while reading state file: {}", error)),
+        }
+    }
+
+    /// Writes state to a temporary file which then replaces the state file, so
+    /// that the state file is never left partially written.
+    fn save(&self, state: &State) -> Result<()> {
+        let mut json = serde_json::to_value(state)?;
+        json["version"] = STATE_VERSION.into();
+        let json = serde_json::to_string(&json)?;
+
+        self.rotate_backups()?;
+
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Parses state file contents, migrating them from older versions first.
+fn parse_state(json: &str, tz: &Tz) -> Result<State> {
+    let mut json: Value = serde_json::from_str(json)?;
+
+    let version = json.get("version").and_then(Value::as_u64).unwrap_or(0);
+    migrate_state(&mut json, version, tz)?;
+
+    Ok(serde_json::from_value(json)?)
+}
+
+/// Migrates state as JSON from given version to `STATE_VERSION`.
+pub(super) fn migrate_state(json: &mut Value, version: u64, tz: &Tz) -> Result<()> {
+    if version > STATE_VERSION {
+        return Err(eyre!(
+            "State version {} is newer than supported version {}",
+            version,
+            STATE_VERSION
+        ));
+    }
+
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(json, tz);
+    }
+
+    Ok(())
+}
+
+/// Migrates state files without a version (version 0).
+///
+/// Migrates queues from state files written before queues were tied to a
+/// date, where `timeout` was a bare `HH:MM:SS` time and timed queue ids were
+/// `HH:MM`. Such queues are moved to the next occurrence of their time.
+fn migrate_legacy_timeouts(json: &mut Value, tz: &Tz) {
+    let now = Utc::now().with_timezone(tz);
+
+    let chats = json.get_mut("chats").and_then(Value::as_object_mut);
+
+    for chat in chats.into_iter().flat_map(|chats| chats.values_mut()) {
+        let Some(queues) = chat.get_mut("queues").and_then(Value::as_object_mut) else {
+            continue;
+        };
+
+        let migrated: Map<String, Value> = std::mem::take(queues)
+            .into_iter()
+            .map(|(queue_id, mut queue)| {
+                let legacy_timeout = queue
+                    .get("timeout")
+                    .and_then(Value::as_str)
+                    .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M:%S%.f").ok());
+
+                let Some(time) = legacy_timeout else {
+                    return (queue_id, queue);
+                };
+
+                let t = QueueDay::Next.resolve(time, &now).unwrap_or(now);
+                queue["timeout"] = serde_json::json!(t.with_timezone(&Utc));
+
+                let queue_id = if queue_id.is_empty() {
+                    queue_id
+                } else {
+                    QueueId::timed(&t).as_str().to_string()
+                };
+
+                (queue_id, queue)
+            })
+            .collect();
+
+        *queues = migrated;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_state_files() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let json = r#"{"chats":{"1":{"queues":{"19:30":{"players":["alice"],"timeout":"19:30:00","add_cmd":"/1930"}}}}}"#;
+
+        let state = parse_state(json, &tz).unwrap();
+        let queues = &state.chats[&teloxide::types::ChatId(1)].queues;
+        assert!(queues
+            .keys()
+            .all(|queue_id| queue_id.local_time().is_some()));
+
+        let json = format!(r#"{{"version":{},"chats":{{}}}}"#, STATE_VERSION + 1);
+        assert!(parse_state(&json, &tz).is_err());
+    }
+}
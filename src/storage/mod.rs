@@ -0,0 +1,30 @@
+use crate::{settings::StorageBackend, state::State};
+use chrono_tz::Tz;
+use color_eyre::Result;
+use std::sync::Arc;
+
+pub mod json;
+pub mod sqlite;
+
+/// Persistent storage of bot state.
+///
+/// Methods block on IO, so async code should call them via
+/// `tokio::task::spawn_blocking`.
+pub trait Storage: Send + Sync {
+    /// Reads stored state. Returns default (empty) state if nothing has been
+    /// stored yet.
+    fn load(&self, tz: &Tz) -> Result<State>;
+
+    /// Stores given state, replacing previously stored state.
+    fn save(&self, state: &State) -> Result<()>;
+}
+
+/// Opens storage of given backend at given path.
+pub fn open(backend: StorageBackend, path: &str) -> Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match backend {
+        StorageBackend::Json => Arc::new(json::JsonStorage::new(path)),
+        StorageBackend::Sqlite => Arc::new(sqlite::SqliteStorage::open(path)?),
+    };
+
+    Ok(storage)
+}
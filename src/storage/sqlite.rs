@@ -0,0 +1,778 @@
+use crate::{
+    state::{
+        AutoAddRule, Availability, Chat, Draft, Queue, QueueRecord, RecurringQueue, State,
+        Subscription,
+    },
+    storage::{
+        json::{migrate_state, STATE_VERSION},
+        Storage,
+    },
+    types::{Player, QueueId},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use color_eyre::{eyre::eyre, Result};
+use rusqlite::{params, Connection, Transaction};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::HashMap, sync::Mutex};
+
+/// Schema migrations. The migration at index `i` migrates the database from
+/// schema version `i` to `i + 1`, with the version kept in `user_version`.
+const SCHEMA_MIGRATIONS: [&str; 4] = [
+    r#"
+    CREATE TABLE players (
+        user_id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+
+    CREATE TABLE chats (
+        chat_id INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+
+    CREATE TABLE queues (
+        chat_id INTEGER NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+        queue_id TEXT NOT NULL,
+        timeout TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (chat_id, queue_id)
+    );
+"#,
+    r#"
+    CREATE TABLE queue_history (
+        chat_id INTEGER NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+        finished_at TEXT NOT NULL,
+        queue_id TEXT NOT NULL,
+        filled INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (chat_id, finished_at, queue_id)
+    );
+"#,
+    // Rows written before state versions were tracked are of version 1.
+    r#"
+    CREATE TABLE state_version (
+        version INTEGER NOT NULL
+    );
+
+    INSERT INTO state_version (version) VALUES (1);
+"#,
+    // Moves chat queue sizes, queue settings and queued players out of the
+    // JSON columns. Players of older state versions may be plain names.
+    r#"
+    ALTER TABLE chats ADD COLUMN queue_size INTEGER;
+
+    UPDATE chats SET
+        queue_size = json_extract(data, '$.queue_size'),
+        data = json_remove(data, '$.queue_size');
+
+    ALTER TABLE queues ADD COLUMN add_cmd TEXT NOT NULL DEFAULT '';
+    ALTER TABLE queues ADD COLUMN size INTEGER;
+
+    CREATE TABLE queue_players (
+        chat_id INTEGER NOT NULL,
+        queue_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        tier TEXT NOT NULL,
+        user_id INTEGER,
+        name TEXT NOT NULL,
+        PRIMARY KEY (chat_id, queue_id, position),
+        FOREIGN KEY (chat_id, queue_id)
+            REFERENCES queues (chat_id, queue_id) ON DELETE CASCADE
+    );
+
+    INSERT INTO queue_players (chat_id, queue_id, position, tier, user_id, name)
+    SELECT
+        queues.chat_id,
+        queues.queue_id,
+        player.key,
+        CASE WHEN EXISTS (
+            SELECT 1 FROM json_each(queues.data, '$.reserve_only') AS reserve
+            WHERE reserve.value = player.value
+        ) THEN 'reserve' ELSE 'yes' END,
+        CASE player.type WHEN 'object' THEN json_extract(player.value, '$.id') END,
+        CASE player.type WHEN 'object' THEN json_extract(player.value, '$.name')
+            ELSE player.value END
+    FROM queues, json_each(queues.data, '$.players') AS player;
+
+    INSERT INTO queue_players (chat_id, queue_id, position, tier, user_id, name)
+    SELECT
+        queues.chat_id,
+        queues.queue_id,
+        COALESCE(json_array_length(queues.data, '$.players'), 0) + player.key,
+        'maybe',
+        CASE player.type WHEN 'object' THEN json_extract(player.value, '$.id') END,
+        CASE player.type WHEN 'object' THEN json_extract(player.value, '$.name')
+            ELSE player.value END
+    FROM queues, json_each(queues.data, '$.maybe') AS player;
+
+    UPDATE queues SET
+        add_cmd = COALESCE(json_extract(data, '$.add_cmd'), ''),
+        size = json_extract(data, '$.size'),
+        data = json_remove(
+            data, '$.timeout', '$.add_cmd', '$.size', '$.players', '$.reserve_only', '$.maybe'
+        );
+"#,
+];
+
+/// Tiers of players in the queue_players table. Players of the `TIER_YES` and
+/// `TIER_RESERVE` tiers are in the queue's player order, followed by players
+/// of the `TIER_MAYBE` tier.
+const TIER_YES: &str = "yes";
+const TIER_RESERVE: &str = "reserve";
+const TIER_MAYBE: &str = "maybe";
+
+/// Formats a point in time for a text column, so that columns sort in
+/// chronological order.
+fn fmt_timestamp(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Chat fields stored in the chats table. Queues and queue history have
+/// tables of their own.
+#[derive(PartialEq, Serialize)]
+struct ChatSettings<'a> {
+    #[serde(skip)]
+    queue_size: Option<usize>,
+    recurring_queues: &'a Vec<RecurringQueue>,
+    subscriptions: &'a Vec<Subscription>,
+    auto_add_rules: &'a Vec<AutoAddRule>,
+    availability: &'a Vec<Availability>,
+    drafts: &'a HashMap<QueueId, Draft>,
+}
+
+impl<'a> From<&'a Chat> for ChatSettings<'a> {
+    fn from(chat: &'a Chat) -> Self {
+        let Chat {
+            queues: _,
+            history: _,
+            queue_size,
+            recurring_queues,
+            subscriptions,
+            auto_add_rules,
+            availability,
+            drafts,
+        } = chat;
+
+        ChatSettings {
+            queue_size: *queue_size,
+            recurring_queues,
+            subscriptions,
+            auto_add_rules,
+            availability,
+            drafts,
+        }
+    }
+}
+
+/// Row of the queues table: timeout, add command, size and the rest of the
+/// queue as JSON.
+type QueueRow = (String, String, Option<i64>, String);
+
+/// Row of the queue_players table: tier, user id and name.
+type QueuePlayerRow = (String, Option<i64>, String);
+
+/// Splits a queue into its row and the rows of its players, in position
+/// order.
+fn queue_rows(queue: &Queue) -> Result<(QueueRow, Vec<QueuePlayerRow>)> {
+    let mut data = serde_json::to_value(queue)?;
+    let data = data
+        .as_object_mut()
+        .ok_or_else(|| eyre!("Queue must serialize to an object"))?;
+
+    let mut take_players = |field: &str| -> Result<Vec<Player>> {
+        let players = data.remove(field).unwrap_or_default();
+        Ok(serde_json::from_value(players)?)
+    };
+    let players = take_players("players")?;
+    let reserve_only = take_players("reserve_only")?;
+    let maybe = take_players("maybe")?;
+
+    for field in ["timeout", "add_cmd", "size"] {
+        data.remove(field);
+    }
+
+    let player_row = |tier: &str, player: Player| -> QueuePlayerRow {
+        (
+            tier.to_string(),
+            player.id.map(|id| id.0 as i64),
+            player.name.to_string(),
+        )
+    };
+
+    let mut player_rows = vec![];
+    for player in players {
+        let tier = if reserve_only.contains(&player) {
+            TIER_RESERVE
+        } else {
+            TIER_YES
+        };
+        player_rows.push(player_row(tier, player));
+    }
+    for player in maybe {
+        player_rows.push(player_row(TIER_MAYBE, player));
+    }
+
+    let row = (
+        fmt_timestamp(&queue.timeout),
+        queue.add_cmd.clone(),
+        Some(queue.size() as i64),
+        Value::Object(data.clone()).to_string(),
+    );
+
+    Ok((row, player_rows))
+}
+
+/// Rows of each table, keyed by primary key.
+#[derive(Default)]
+struct Rows {
+    /// Version of the state format the JSON columns are in, see
+    /// `json::STATE_VERSION`.
+    version: u64,
+    players: HashMap<i64, String>,
+    /// Queue size and the rest of the chat settings as JSON.
+    chats: HashMap<i64, (Option<i64>, String)>,
+    queues: HashMap<(i64, String), QueueRow>,
+    /// Players of each queue, in position order.
+    queue_players: HashMap<(i64, String), Vec<QueuePlayerRow>>,
+    queue_history: HashMap<(i64, String, String), (bool, String)>,
+}
+
+impl Rows {
+    /// Joins table rows back into state, migrating it from older versions
+    /// first.
+    fn to_state(&self, tz: &Tz) -> Result<State> {
+        let mut chats: HashMap<i64, Value> = HashMap::new();
+
+        for (chat_id, (queue_size, data)) in &self.chats {
+            let mut data: Value = serde_json::from_str(data)?;
+            data["queue_size"] = (*queue_size).into();
+            data["queues"] = Value::Object(Default::default());
+            data["history"] = Value::Array(vec![]);
+            chats.insert(*chat_id, data);
+        }
+
+        for (key @ (chat_id, queue_id), (timeout, add_cmd, size, data)) in &self.queues {
+            let chat = chats
+                .get_mut(chat_id)
+                .ok_or_else(|| eyre!("Queue {} refers to unknown chat {}", queue_id, chat_id))?;
+
+            let mut queue: Value = serde_json::from_str(data)?;
+            queue["timeout"] = timeout.as_str().into();
+            queue["add_cmd"] = add_cmd.as_str().into();
+            if let Some(size) = size {
+                queue["size"] = (*size).into();
+            }
+
+            let (mut players, mut reserve_only, mut maybe) = (vec![], vec![], vec![]);
+            for (tier, user_id, name) in self.queue_players.get(key).into_iter().flatten() {
+                let player = serde_json::json!({ "id": user_id, "name": name });
+
+                match tier.as_str() {
+                    TIER_MAYBE => maybe.push(player),
+                    TIER_RESERVE => {
+                        reserve_only.push(player.clone());
+                        players.push(player);
+                    }
+                    _ => players.push(player),
+                }
+            }
+            queue["players"] = Value::Array(players);
+            queue["reserve_only"] = Value::Array(reserve_only);
+            queue["maybe"] = Value::Array(maybe);
+
+            chat["queues"][queue_id] = queue;
+        }
+
+        let mut history: Vec<_> = self.queue_history.iter().collect();
+        history.sort_by_key(|(key, _)| *key);
+
+        for ((chat_id, _, queue_id), (_, data)) in history {
+            let chat = chats
+                .get_mut(chat_id)
+                .and_then(|chat| chat["history"].as_array_mut())
+                .ok_or_else(|| eyre!("Queue {} refers to unknown chat {}", queue_id, chat_id))?;
+
+            chat.push(serde_json::from_str(data)?);
+        }
+
+        let users: HashMap<String, Value> = self
+            .players
+            .iter()
+            .map(|(user_id, name)| (user_id.to_string(), Value::from(name.as_str())))
+            .collect();
+
+        let chats: HashMap<String, Value> = chats
+            .into_iter()
+            .map(|(chat_id, data)| (chat_id.to_string(), data))
+            .collect();
+
+        let mut json = serde_json::json!({ "chats": chats, "users": users });
+        migrate_state(&mut json, self.version, tz)?;
+
+        Ok(serde_json::from_value(json)?)
+    }
+}
+
+/// Stores state in an embedded SQLite database, with tables for players,
+/// chats, queues, queued players and queue history. Only rows of what
+/// changed since the last save are built and written.
+pub struct SqliteStorage {
+    /// Connection to the database along with the state its rows hold, if
+    /// known.
+    db: Mutex<(Connection, Option<State>)>,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the database at given path, migrating its schema to
+    /// the latest version.
+    pub fn open(path: &str) -> Result<SqliteStorage> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+
+        migrate(&mut conn)?;
+
+        Ok(SqliteStorage {
+            db: Mutex::new((conn, None)),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, tz: &Tz) -> Result<State> {
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| eyre!("Database lock poisoned"))?;
+        let (conn, stored) = &mut *db;
+
+        let rows = read_rows(conn)?;
+        let state = rows.to_state(tz)?;
+
+        // Rows of older state versions are rewritten on the next save.
+        *stored = (rows.version == STATE_VERSION).then(|| state.clone());
+
+        Ok(state)
+    }
+
+    fn save(&self, state: &State) -> Result<()> {
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| eyre!("Database lock poisoned"))?;
+        let (conn, stored) = &mut *db;
+
+        let tx = conn.transaction()?;
+
+        // Replace all rows if it's unknown what they hold. Should the save
+        // fail, the rows are replaced on the next save as well.
+        let previous = match stored.take() {
+            Some(previous) => previous,
+            None => {
+                tx.execute_batch("DELETE FROM players; DELETE FROM chats;")?;
+                tx.execute("UPDATE state_version SET version = ?1", [STATE_VERSION])?;
+                State::default()
+            }
+        };
+
+        write_changes(&tx, &previous, state)?;
+
+        tx.commit()?;
+        *stored = Some(state.clone());
+
+        Ok(())
+    }
+}
+
+/// Writes rows of `state` that differ from `previous`, the state the database
+/// currently holds.
+fn write_changes(tx: &Transaction, previous: &State, state: &State) -> Result<()> {
+    for user_id in previous.users.keys() {
+        if !state.users.contains_key(user_id) {
+            tx.execute("DELETE FROM players WHERE user_id = ?1", [user_id.0 as i64])?;
+        }
+    }
+
+    for (user_id, name) in &state.users {
+        if previous.users.get(user_id) != Some(name) {
+            tx.execute(
+                "INSERT OR REPLACE INTO players (user_id, name) VALUES (?1, ?2)",
+                params![user_id.0 as i64, name.to_string()],
+            )?;
+        }
+    }
+
+    let no_chat = Chat::default();
+
+    for (chat_id, chat) in &state.chats {
+        let previous_chat = previous.chats.get(chat_id);
+        let settings = ChatSettings::from(chat);
+
+        if previous_chat.map(ChatSettings::from).as_ref() != Some(&settings) {
+            tx.execute(
+                "INSERT INTO chats (chat_id, queue_size, data) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (chat_id) DO UPDATE SET
+                     queue_size = excluded.queue_size,
+                     data = excluded.data",
+                params![
+                    chat_id.0,
+                    settings.queue_size.map(|size| size as i64),
+                    serde_json::to_string(&settings)?
+                ],
+            )?;
+        }
+
+        let previous_chat = previous_chat.unwrap_or(&no_chat);
+        write_queues(tx, chat_id.0, &previous_chat.queues, &chat.queues)?;
+        write_history(tx, chat_id.0, &previous_chat.history, &chat.history)?;
+    }
+
+    // Deleting chats last also deletes any of their rows left over.
+    for chat_id in previous.chats.keys() {
+        if !state.chats.contains_key(chat_id) {
+            tx.execute("DELETE FROM chats WHERE chat_id = ?1", [chat_id.0])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the queues of a chat that differ from `previous`, along with their
+/// players.
+fn write_queues(
+    tx: &Transaction,
+    chat_id: i64,
+    previous: &HashMap<QueueId, Queue>,
+    queues: &HashMap<QueueId, Queue>,
+) -> Result<()> {
+    for queue_id in previous.keys() {
+        if !queues.contains_key(queue_id) {
+            tx.execute(
+                "DELETE FROM queues WHERE chat_id = ?1 AND queue_id = ?2",
+                params![chat_id, queue_id.as_str()],
+            )?;
+        }
+    }
+
+    for (queue_id, queue) in queues {
+        if previous.get(queue_id) == Some(queue) {
+            continue;
+        }
+
+        let ((timeout, add_cmd, size, data), players) = queue_rows(queue)?;
+
+        tx.execute(
+            "INSERT INTO queues (chat_id, queue_id, timeout, add_cmd, size, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (chat_id, queue_id) DO UPDATE SET
+                 timeout = excluded.timeout,
+                 add_cmd = excluded.add_cmd,
+                 size = excluded.size,
+                 data = excluded.data",
+            params![chat_id, queue_id.as_str(), timeout, add_cmd, size, data],
+        )?;
+
+        tx.execute(
+            "DELETE FROM queue_players WHERE chat_id = ?1 AND queue_id = ?2",
+            params![chat_id, queue_id.as_str()],
+        )?;
+
+        for (position, (tier, user_id, name)) in players.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO queue_players (chat_id, queue_id, position, tier, user_id, name)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![chat_id, queue_id.as_str(), position, tier, user_id, name],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the queue history records of a chat that differ from `previous`.
+fn write_history(
+    tx: &Transaction,
+    chat_id: i64,
+    previous: &[QueueRecord],
+    history: &[QueueRecord],
+) -> Result<()> {
+    if previous == history {
+        return Ok(());
+    }
+
+    fn key(record: &QueueRecord) -> (DateTime<Utc>, &QueueId) {
+        (record.finished_at, &record.queue_id)
+    }
+
+    let previous: HashMap<_, _> = previous
+        .iter()
+        .map(|record| (key(record), record))
+        .collect();
+    let current: HashMap<_, _> = history.iter().map(|record| (key(record), record)).collect();
+
+    for key @ (finished_at, queue_id) in previous.keys() {
+        if !current.contains_key(key) {
+            tx.execute(
+                "DELETE FROM queue_history
+                 WHERE chat_id = ?1 AND finished_at = ?2 AND queue_id = ?3",
+                params![chat_id, fmt_timestamp(finished_at), queue_id.as_str()],
+            )?;
+        }
+    }
+
+    for (key @ (finished_at, queue_id), record) in &current {
+        if previous.get(key) == Some(record) {
+            continue;
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO queue_history
+             (chat_id, finished_at, queue_id, filled, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chat_id,
+                fmt_timestamp(finished_at),
+                queue_id.as_str(),
+                record.filled,
+                serde_json::to_string(record)?
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Runs schema migrations the database hasn't seen yet.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    if version > SCHEMA_MIGRATIONS.len() {
+        return Err(eyre!(
+            "Database schema version {} is newer than supported version {}",
+            version,
+            SCHEMA_MIGRATIONS.len()
+        ));
+    }
+
+    for (n, migration) in SCHEMA_MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", n + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Reads all rows from the database.
+fn read_rows(conn: &Connection) -> Result<Rows> {
+    let mut rows = Rows {
+        version: conn.query_row("SELECT version FROM state_version", [], |row| row.get(0))?,
+        ..Default::default()
+    };
+
+    let mut stmt = conn.prepare("SELECT user_id, name FROM players")?;
+    for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+        let (user_id, name) = row?;
+        rows.players.insert(user_id, name);
+    }
+
+    let mut stmt = conn.prepare("SELECT chat_id, queue_size, data FROM chats")?;
+    for row in stmt.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))? {
+        let (chat_id, row) = row?;
+        rows.chats.insert(chat_id, row);
+    }
+
+    let mut stmt =
+        conn.prepare("SELECT chat_id, queue_id, timeout, add_cmd, size, data FROM queues")?;
+    for row in stmt.query_map([], |row| {
+        Ok((
+            (row.get(0)?, row.get(1)?),
+            (row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?),
+        ))
+    })? {
+        let (key, row) = row?;
+        rows.queues.insert(key, row);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, queue_id, tier, user_id, name FROM queue_players
+         ORDER BY chat_id, queue_id, position",
+    )?;
+    for row in stmt.query_map([], |row| {
+        Ok((
+            (row.get(0)?, row.get(1)?),
+            (row.get(2)?, row.get(3)?, row.get(4)?),
+        ))
+    })? {
+        let (key, row) = row?;
+        rows.queue_players.entry(key).or_default().push(row);
+    }
+
+    let mut stmt =
+        conn.prepare("SELECT chat_id, finished_at, queue_id, filled, data FROM queue_history")?;
+    for row in stmt.query_map([], |row| {
+        Ok((
+            (row.get(0)?, row.get(1)?, row.get(2)?),
+            (row.get(3)?, row.get(4)?),
+        ))
+    })? {
+        let (key, row) = row?;
+        rows.queue_history.insert(key, row);
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{
+            test_util::{add, players, CHAT_ID},
+            JoinMode,
+        },
+        types::{QueueId, Username},
+    };
+
+    #[test]
+    fn saves_and_loads_state() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let player = &players(1)[0];
+
+        let (state, ..) = add(
+            &State::default(),
+            &QueueId::instant(),
+            None,
+            player,
+            JoinMode::Toggle,
+        );
+        let state = state.identify_player(player).unwrap_or(state);
+        storage.save(&state).unwrap();
+
+        let loaded = storage.load(&tz).unwrap();
+        let queue = &loaded.chats[&CHAT_ID].queues[&QueueId::instant()];
+        assert_eq!(queue.num_players(), 1);
+        assert_eq!(loaded.users.len(), 1);
+
+        // Timing out the queue moves it from its row to the queue history.
+        let (state, _) = state.time_out_chat_queue(&CHAT_ID, &QueueId::instant());
+        storage.save(&state).unwrap();
+
+        let rows = read_rows(&storage.db.lock().unwrap().0).unwrap();
+        assert!(rows.queues.is_empty());
+        assert_eq!(rows.chats.len(), 1);
+        assert_eq!(rows.queue_history.len(), 1);
+
+        let loaded = storage.load(&tz).unwrap();
+        assert_eq!(loaded.chats[&CHAT_ID].history.len(), 1);
+    }
+
+    #[test]
+    fn migrates_rows_of_older_state_versions() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let storage = SqliteStorage::open(":memory:").unwrap();
+
+        {
+            let db = storage.db.lock().unwrap();
+            db.0.execute_batch(
+                r#"
+                UPDATE state_version SET version = 0;
+                INSERT INTO chats (chat_id, data) VALUES (1, '{}');
+                INSERT INTO queues (chat_id, queue_id, timeout, add_cmd, data)
+                    VALUES (1, '19:30', '19:30:00', '/1930', '{}');
+                INSERT INTO queue_players (chat_id, queue_id, position, tier, name)
+                    VALUES (1, '19:30', 0, 'yes', 'alice');
+                "#,
+            )
+            .unwrap();
+        }
+
+        let state = storage.load(&tz).unwrap();
+        let queues = &state.chats[&CHAT_ID].queues;
+        assert!(queues
+            .keys()
+            .all(|queue_id| queue_id.local_time().is_some()));
+
+        storage.save(&state).unwrap();
+        let rows = read_rows(&storage.db.lock().unwrap().0).unwrap();
+        assert_eq!(rows.version, STATE_VERSION);
+    }
+
+    #[test]
+    fn stores_queued_players_by_tier() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let queue_id = QueueId::instant();
+        let players = players(3);
+
+        let (state, ..) = add(
+            &State::default(),
+            &queue_id,
+            None,
+            &players[0],
+            JoinMode::Toggle,
+        );
+        let (state, ..) = add(&state, &queue_id, None, &players[1], JoinMode::Reserve);
+        let (state, ..) = add(&state, &queue_id, None, &players[2], JoinMode::Maybe);
+        storage.save(&state).unwrap();
+
+        let rows = read_rows(&storage.db.lock().unwrap().0).unwrap();
+        let tiers: Vec<&str> = rows.queue_players[&(CHAT_ID.0, String::new())]
+            .iter()
+            .map(|(tier, ..)| tier.as_str())
+            .collect();
+        assert_eq!(tiers, vec![TIER_YES, TIER_RESERVE, TIER_MAYBE]);
+
+        let loaded = storage.load(&tz).unwrap();
+        let queue = &loaded.chats[&CHAT_ID].queues[&queue_id];
+        assert_eq!(queue.player_tier(&players[0]), Some(JoinMode::Yes));
+        assert_eq!(queue.player_tier(&players[1]), Some(JoinMode::Reserve));
+        assert_eq!(queue.player_tier(&players[2]), Some(JoinMode::Maybe));
+    }
+
+    #[test]
+    fn migrates_json_columns_to_tables() {
+        let tz = chrono_tz::Europe::Helsinki;
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        for migration in &SCHEMA_MIGRATIONS[..3] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute_batch(
+            r#"
+            PRAGMA user_version = 3;
+            INSERT INTO chats (chat_id, data) VALUES (1, '{"queue_size":5}');
+            INSERT INTO queues (chat_id, queue_id, timeout, data)
+                VALUES (1, '', '2024-12-24T17:30:00.000000Z', '{
+                    "players":[{"id":1,"name":"p1"},{"id":2,"name":"p2"}],
+                    "reserve_only":[{"id":2,"name":"p2"}],
+                    "maybe":["p3"],
+                    "timeout":"2024-12-24T17:30:00Z",
+                    "add_cmd":"/add",
+                    "size":5
+                }');
+            "#,
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        let rows = read_rows(&conn).unwrap();
+        assert_eq!(rows.chats[&1], (Some(5), String::from("{}")));
+
+        let state = rows.to_state(&tz).unwrap();
+        let chat = &state.chats[&CHAT_ID];
+        assert_eq!(chat.queue_size, Some(5));
+
+        let players = players(2);
+        let queue = &chat.queues[&QueueId::instant()];
+        assert_eq!(queue.add_cmd, "/add");
+        assert_eq!(queue.size(), 5);
+        assert_eq!(queue.player_tier(&players[0]), Some(JoinMode::Yes));
+        assert_eq!(queue.player_tier(&players[1]), Some(JoinMode::Reserve));
+        let maybe = Player::new(None, Username::new(String::from("p3")));
+        assert_eq!(queue.player_tier(&maybe), Some(JoinMode::Maybe));
+    }
+}
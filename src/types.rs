@@ -1,4 +1,10 @@
+use chrono::{DateTime, NaiveDateTime};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+
+/// Format of timed queue ids, in the bot's local timezone.
+const QUEUE_ID_FORMAT: &str = "%Y-%m-%d %H:%M";
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct QueueId(String);
@@ -8,20 +14,40 @@ impl QueueId {
         QueueId(id)
     }
 
+    /// Id of the chat's instant queue.
+    pub fn instant() -> QueueId {
+        QueueId::new(String::new())
+    }
+
+    /// Id of a timed queue starting at `t`, e.g. `2024-12-24 19:30`.
+    pub fn timed(t: &DateTime<Tz>) -> QueueId {
+        QueueId::new(t.format(QUEUE_ID_FORMAT).to_string())
+    }
+
     pub fn is_instant_queue(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// Start time of a timed queue, in the bot's local timezone.
+    pub fn local_time(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(&self.0, QUEUE_ID_FORMAT).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl std::fmt::Display for QueueId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = if self.is_instant_queue() {
-            "Instant"
-        } else {
-            &self.0
-        };
+        if self.is_instant_queue() {
+            return write!(f, "Instant");
+        }
 
-        write!(f, "{}", s)
+        match self.local_time() {
+            Some(t) => write!(f, "{}", t.format("%a %d.%m. %H:%M")),
+            None => write!(f, "{}", self.0),
+        }
     }
 }
 
@@ -40,6 +66,69 @@ impl std::fmt::Display for Username {
     }
 }
 
+/// A player taking part in queues.
+///
+/// Players are identified by their Telegram user id. Players that were added
+/// by `@username` before the bot saw them have no id, and are identified by
+/// name until they interact with the bot themselves.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(from = "PlayerRepr")]
+pub struct Player {
+    pub id: Option<UserId>,
+    pub name: Username,
+}
+
+impl Player {
+    pub fn new(id: Option<UserId>, name: Username) -> Player {
+        Player { id, name }
+    }
+
+    /// Returns the value that identifies this player.
+    fn key(&self) -> (Option<UserId>, Option<&Username>) {
+        match self.id {
+            Some(id) => (Some(id), None),
+            None => (None, Some(&self.name)),
+        }
+    }
+}
+
+impl PartialEq for Player {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Player {}
+
+impl std::hash::Hash for Player {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl std::fmt::Display for Player {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Serialized forms of a player. Older state files only stored usernames.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlayerRepr {
+    Player { id: Option<UserId>, name: Username },
+    Username(Username),
+}
+
+impl From<PlayerRepr> for Player {
+    fn from(repr: PlayerRepr) -> Self {
+        match repr {
+            PlayerRepr::Player { id, name } => Player::new(id, name),
+            PlayerRepr::Username(name) => Player::new(None, name),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct SteamID(String);
 
@@ -1,13 +1,19 @@
 use crate::{
+    command::CallbackCmd,
     state::{AddRemovePlayerOp, Queue},
-    types::{QueueId, Username},
+    types::{Player, QueueId, Username},
 };
-use chrono::NaiveTime;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
 use teloxide::{
-    payloads::SendMessageSetters,
+    payloads::{EditMessageTextSetters, SendMessageSetters},
     prelude::{Request, Requester},
-    types::{ChatId, InputFile, ParseMode, User},
-    Bot,
+    types::{
+        ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Message, MessageId,
+        ParseMode, User,
+    },
+    utils::html::{escape, user_mention},
+    ApiError, Bot, RequestError,
 };
 
 /// Tries in order to extract a user's:
@@ -27,9 +33,31 @@ pub fn mk_username(user: &User) -> Username {
     Username::new(str)
 }
 
-/// Formats a Chrono NaiveTime using our desired time format.
-pub fn fmt_naive_time(t: &NaiveTime) -> String {
-    t.format("%H:%M").to_string()
+/// Constructs a player from a Telegram user.
+pub fn mk_player(user: &User) -> Player {
+    Player::new(Some(user.id), mk_username(user))
+}
+
+/// Reply for when a name given as an argument matches several users.
+pub fn mk_ambiguous_name_msg(name: &Username) -> String {
+    format!(
+        "Several users go by {}, can't tell which one you mean.",
+        escape(&name.to_string())
+    )
+}
+
+/// Constructs the shortest command that adds to a timed queue at `t`.
+///
+/// The command stays valid for as long as `t` is in the future.
+pub fn mk_add_cmd(t: &DateTime<Tz>, now: &DateTime<Tz>) -> String {
+    if *t - *now < Duration::days(1) {
+        t.format("/%H%M").to_string()
+    } else if *t - *now < Duration::weeks(1) {
+        let weekday = t.format("%a").to_string().to_lowercase();
+        format!("/{}{}", weekday, t.format("%H%M"))
+    } else {
+        t.format("/%H%M %Y-%m-%d").to_string()
+    }
 }
 
 /// Helper for sending Telegram messages (and logging errors to stderr).
@@ -53,6 +81,60 @@ pub async fn send_msg(bot: &Bot, chat_id: &ChatId, text: &str, markdown: bool) {
     }
 }
 
+/// Helper for sending Telegram messages with an inline keyboard (and logging
+/// errors to stderr). Returns the sent message.
+pub async fn send_msg_with_keyboard(
+    bot: &Bot,
+    chat_id: &ChatId,
+    text: &str,
+    keyboard: InlineKeyboardMarkup,
+) -> Option<Message> {
+    let request = bot
+        .send_message(*chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard);
+
+    let res = request.send().await;
+
+    match res {
+        Ok(msg) => Some(msg),
+        Err(error) => {
+            eprintln!("Error while sending Telegram message: {}", error);
+            None
+        }
+    }
+}
+
+/// Helper for editing Telegram messages (and logging errors to stderr). The
+/// inline keyboard is removed unless one is given.
+///
+/// Returns whether the message now has the given content.
+pub async fn edit_msg(
+    bot: &Bot,
+    chat_id: &ChatId,
+    msg_id: MessageId,
+    text: &str,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> bool {
+    let mut request = bot
+        .edit_message_text(*chat_id, msg_id, text)
+        .parse_mode(ParseMode::Html);
+
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+
+    let res = request.send().await;
+
+    match res {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => true,
+        Err(error) => {
+            eprintln!("Error while editing Telegram message: {}", error);
+            false
+        }
+    }
+}
+
 /// Helper for sending Telegram photo (and logging errors to stderr).
 pub async fn send_photo(bot: &Bot, chat_id: &ChatId, photo: InputFile) {
     let request = bot.send_photo(*chat_id, photo);
@@ -64,28 +146,134 @@ pub async fn send_photo(bot: &Bot, chat_id: &ChatId, photo: InputFile) {
     }
 }
 
+/// Helper for sending Telegram documents (and logging errors to stderr).
+pub async fn send_document(bot: &Bot, chat_id: &ChatId, document: InputFile) {
+    let request = bot.send_document(*chat_id, document);
+
+    let res = request.send().await;
+
+    if let Err(error) = res {
+        eprintln!("Error while sending Telegram document message: {}", error);
+    }
+}
+
 /// Constructs a status message describing current queue status.
 pub fn mk_queue_status_msg(queue: &Queue, queue_id: &QueueId, op: &AddRemovePlayerOp) -> String {
+    if queue.ready_check.is_some() {
+        return format!(
+            "{} queue: {}.\n{}",
+            queue_id,
+            escape(&op.to_string()),
+            mk_ready_check_str(queue, false)
+        );
+    }
+
     let players_str = mk_players_str(queue, false, false);
 
     format!(
         "{} queue: {}.\n{}.\nUse {} to add/remove yourself from the queue!",
-        queue_id, op, players_str, queue.add_cmd,
+        queue_id,
+        escape(&op.to_string()),
+        players_str,
+        queue.add_cmd,
+    )
+}
+
+/// Describes an ongoing ready check of a queue. When `highlight` is set,
+/// players who haven't confirmed yet are mentioned.
+pub fn mk_ready_check_str(queue: &Queue, highlight: bool) -> String {
+    let Some(ready_check) = &queue.ready_check else {
+        return String::new();
+    };
+
+    let (_, reserve) = queue.get_players();
+    let unconfirmed = queue.unconfirmed_players();
+
+    let ready = if ready_check.confirmed.is_empty() {
+        String::from("nobody yet")
+    } else {
+        fmt_players(&ready_check.confirmed, false)
+    };
+
+    let reserve = reserve
+        .map(|reserve| format!("\nReserve: {}", fmt_players(&reserve, false)))
+        .unwrap_or_default();
+
+    // Round up, so that the last minute isn't shown as zero.
+    let seconds = (ready_check.deadline - Utc::now()).num_seconds().max(0);
+    let minutes = (seconds + 59) / 60;
+
+    format!(
+        "Ready: {}\nWaiting for: {}{}\nConfirm with /ready within {} minutes!",
+        ready,
+        fmt_players(&unconfirmed, highlight),
+        reserve,
+        minutes.max(1)
     )
 }
 
-/// Creates a string containing the list of players in queue.
+/// Constructs the buttons attached to queue status messages: Join/Maybe/Leave,
+/// plus Ready during a ready check.
+pub fn mk_queue_keyboard(queue_id: &QueueId, queue: &Queue) -> InlineKeyboardMarkup {
+    let queue_id = queue_id.clone();
+    let join = CallbackCmd::Join {
+        queue_id: queue_id.clone(),
+    };
+    let maybe = CallbackCmd::Maybe {
+        queue_id: queue_id.clone(),
+    };
+    let leave = CallbackCmd::Leave {
+        queue_id: queue_id.clone(),
+    };
+
+    let mut buttons = vec![
+        InlineKeyboardButton::callback("Join", join.to_data()),
+        InlineKeyboardButton::callback("Maybe", maybe.to_data()),
+        InlineKeyboardButton::callback("Leave", leave.to_data()),
+    ];
+
+    if queue.ready_check.is_some() {
+        let ready = CallbackCmd::Ready { queue_id };
+        buttons.insert(0, InlineKeyboardButton::callback("Ready", ready.to_data()));
+    }
+
+    InlineKeyboardMarkup::new([buttons])
+}
+
+/// Formats a list of players as HTML. When `highlight` is set, players are
+/// mentioned so they get notified.
+pub fn fmt_players(players: &[Player], highlight: bool) -> String {
+    players
+        .iter()
+        .map(|player| {
+            let name = player.name.to_string();
+
+            match (highlight, player.id) {
+                // Text mentions notify users even without a Telegram @username.
+                (true, Some(id)) => user_mention(id, &name),
+                (true, None) => format!("@{}", escape(&name)),
+                (false, _) => escape(&name),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Creates a string containing the list of players in queue, formatted as
+/// HTML. When `highlight` is set, players are mentioned so they get notified.
 pub fn mk_players_str(queue: &Queue, highlight: bool, short: bool) -> String {
     let (players, reserve) = queue.get_players();
 
-    let fmt_usernames = |usernames: Vec<Username>| {
-        usernames
+    let fmt_usernames = |players: Vec<Player>| {
+        players
             .iter()
-            .map(|username| {
-                if highlight {
-                    format!("@{}", username)
+            .map(|player| {
+                let name = fmt_players(std::slice::from_ref(player), highlight);
+
+                if queue.is_auto_added(player) {
+                    format!("{} (auto)", name)
                 } else {
-                    username.to_string()
+                    name
                 }
             })
             .collect::<Vec<String>>()
@@ -103,14 +291,22 @@ pub fn mk_players_str(queue: &Queue, highlight: bool, short: bool) -> String {
 
     let reserve = reserve.map(fmt_usernames);
 
+    // Maybe players are never highlighted, as they haven't committed to play.
+    let maybe = queue.get_maybe_players();
+    let maybe = if maybe.is_empty() {
+        String::new()
+    } else {
+        format!(", Maybe: {}", fmt_players(&maybe, false))
+    };
+
     let title = if short { "" } else { "Players: " };
 
     if let Some(reserve) = reserve {
         format!(
-            "{}{} ({}, Reserve: {})",
-            title, player_count, players, reserve
+            "{}{} ({}, Reserve: {}{})",
+            title, player_count, players, reserve, maybe
         )
     } else {
-        format!("{}{} ({})", title, player_count, players)
+        format!("{}{} ({}{})", title, player_count, players, maybe)
     }
 }